@@ -0,0 +1,260 @@
+//! After Effects and Premiere behind `unmult_core::host::Host`.
+
+use after_effects as ae;
+use unmult_core::buffer::ChannelOrder;
+use unmult_core::host::{Checkout, Depth, Format, Host, HostError, Pixels, PixelsMut, Progress, World, WorldMut, Worlds};
+use unmult_core::rgba_to_yuv::{YuvFormat, YuvMatrix, YuvRange};
+use unmult_core::roi::Rect;
+
+/// `ae::Error` that core errors convert into.
+#[derive(Debug)]
+pub struct AeError(pub ae::Error);
+
+impl From<ae::Error> for AeError {
+    fn from(e: ae::Error) -> Self {
+        AeError(e)
+    }
+}
+
+impl From<HostError> for AeError {
+    fn from(e: HostError) -> Self {
+        log::warn!("{e}");
+        AeError(match e {
+            HostError::Aborted => ae::Error::InterruptCancel,
+            HostError::UnsupportedFormat { .. } | HostError::Buffer(_) => ae::Error::BadCallbackParameter,
+        })
+    }
+}
+
+/// Layer rects of the input and output worlds, handed from SmartPreRender to SmartRender.
+#[derive(Clone, Copy, Debug)]
+pub struct Regions {
+    pub input: Rect,
+    pub output: Rect,
+}
+
+/// Where a command gets its worlds from.
+pub enum Stage {
+    GlobalSetup,
+    PreRender(ae::PreRenderExtra),
+    SmartRender { extra: ae::SmartRenderExtra, regions: Regions },
+    Render { in_layer: ae::Layer, out_layer: ae::Layer },
+}
+
+pub struct AeHost<'a> {
+    in_data: &'a ae::InData,
+    stage: Stage,
+    /// Input result rect from SmartPreRender's checkout.
+    checked_out: Rect,
+    /// Worlds being rendered.
+    layers: Option<(ae::Layer, ae::Layer)>,
+    /// Whether SmartRender's input is checked out and not yet checked back in.
+    input_checked_out: bool,
+    progress: AeProgress<'a>,
+}
+
+impl<'a> AeHost<'a> {
+    pub fn new(in_data: &'a ae::InData, stage: Stage) -> Self {
+        Self { in_data, stage, checked_out: Rect::default(), layers: None, input_checked_out: false, progress: AeProgress(in_data) }
+    }
+
+    /// Pixel format of a world; Premiere may hand over VUYA.
+    fn format(&self, layer: &ae::Layer) -> Result<Format, ae::Error> {
+        let depth = match layer.bit_depth() {
+            8 => Depth::U8,
+            // Both hosts' 16bpc worlds are 0..=32768, not full-range u16.
+            16 => Depth::Ae16,
+            _ => Depth::F32,
+        };
+        let yuv = if self.in_data.is_premiere() { yuv_format(layer.pr_pixel_format()?) } else { None };
+        Ok(Format { depth, yuv })
+    }
+
+    fn order(&self, format: Format) -> ChannelOrder {
+        match (format.yuv, self.in_data.is_premiere()) {
+            (Some(_), _) => ChannelOrder::VUYA,
+            // Premiere hands us Bgra4444_*, After Effects its ARGB worlds.
+            (None, true) => ChannelOrder::Bgra,
+            (None, false) => ChannelOrder::Argb,
+        }
+    }
+}
+
+impl Host for AeHost<'_> {
+    type Error = AeError;
+
+    fn is_premiere(&self) -> bool {
+        self.in_data.is_premiere()
+    }
+
+    fn add_supported_format(&mut self, format: Format) -> Result<(), AeError> {
+        let suite = ae::pf::suites::PixelFormat::new()?;
+        let pr_format = match (format.depth, format.yuv.map(|f| f.matrix)) {
+            (Depth::U8, None) => ae::pr::PixelFormat::Bgra4444_8u,
+            (Depth::Ae16, None) => ae::pr::PixelFormat::Bgra4444_16u,
+            (Depth::F32, None) => ae::pr::PixelFormat::Bgra4444_32f,
+            (Depth::U8, Some(YuvMatrix::Rec601)) => ae::pr::PixelFormat::Vuya4444_8u,
+            (Depth::U8, Some(YuvMatrix::Rec709)) => ae::pr::PixelFormat::Vuya4444_8u709,
+            (Depth::F32, Some(YuvMatrix::Rec601)) => ae::pr::PixelFormat::Vuya4444_32f,
+            (Depth::F32, Some(YuvMatrix::Rec709)) => ae::pr::PixelFormat::Vuya4444_32f709,
+            (Depth::Ae16, Some(_)) => return Err(ae::Error::BadCallbackParameter.into()),
+        };
+        suite.add_supported_pixel_format(self.in_data.effect_ref(), pr_format)?;
+        Ok(())
+    }
+
+    fn output_request(&self) -> Rect {
+        match &self.stage {
+            Stage::PreRender(extra) => to_rect(extra.output_request().rect),
+            _ => Rect::default(),
+        }
+    }
+
+    fn checkout_layer(&mut self, request: Rect) -> Result<Checkout, AeError> {
+        let Stage::PreRender(extra) = &mut self.stage else {
+            return Err(ae::Error::BadCallbackParameter.into());
+        };
+        let mut req = extra.output_request();
+        req.rect = from_rect(request);
+        let in_data = self.in_data;
+        let result = extra.callbacks().checkout_layer(0, 0, &req, in_data.current_time(), in_data.time_step(), in_data.time_scale())?;
+        self.checked_out = to_rect(result.result_rect);
+        Ok(Checkout { result_rect: self.checked_out, max_result_rect: to_rect(result.max_result_rect) })
+    }
+
+    fn set_result_rects(&mut self, result: Rect, max_result: Rect) -> Result<(), AeError> {
+        let Stage::PreRender(extra) = &mut self.stage else {
+            return Err(ae::Error::BadCallbackParameter.into());
+        };
+        extra.union_result_rect(from_rect(result))?;
+        extra.union_max_result_rect(from_rect(max_result))?;
+        // SmartRender's input world covers the checked out result.
+        extra.set_pre_render_data(Regions { input: self.checked_out, output: result });
+        Ok(())
+    }
+
+    fn checkout_worlds(&mut self) -> Result<Option<Worlds<'_, AeError>>, AeError> {
+        let regions = match &mut self.stage {
+            Stage::SmartRender { extra, regions } => {
+                let cb = extra.callbacks();
+                let input = cb.checkout_layer_pixels(0)?;
+                self.input_checked_out = true;
+                let Some(input) = input else {
+                    return Ok(None);
+                };
+                let Some(output) = cb.checkout_output()? else {
+                    return Ok(None);
+                };
+                self.layers = Some((input, output));
+                Some(*regions)
+            }
+            Stage::Render { .. } => {
+                let Stage::Render { in_layer, out_layer } = std::mem::replace(&mut self.stage, Stage::GlobalSetup) else {
+                    unreachable!()
+                };
+                self.layers = Some((in_layer, out_layer));
+                None
+            }
+            _ => return Err(ae::Error::BadCallbackParameter.into()),
+        };
+        let (in_layer, out_layer) = self.layers.as_ref().expect("layers were just checked out");
+        let (in_format, out_format) = (self.format(in_layer)?, self.format(out_layer)?);
+        // Legacy renders get full frames at the layer origin.
+        let regions = regions.unwrap_or(Regions {
+            input: Rect::with_size(0, 0, in_layer.width(), in_layer.height()),
+            output: Rect::with_size(0, 0, out_layer.width(), out_layer.height()),
+        });
+        let (in_order, out_order) = (self.order(in_format), self.order(out_format));
+
+        let (in_layer, out_layer) = self.layers.as_mut().expect("layers were just checked out");
+        let input = World {
+            pixels: pixels(in_format.depth, in_layer.buffer()),
+            stride: in_layer.buffer_stride(),
+            rect: regions.input,
+            order: in_order,
+            yuv: in_format.yuv,
+        };
+        let output = WorldMut {
+            stride: out_layer.buffer_stride(),
+            pixels: pixels_mut(out_format.depth, out_layer.buffer_mut()),
+            rect: regions.output,
+            order: out_order,
+            yuv: out_format.yuv,
+        };
+        Ok(Some(Worlds { input, output, progress: &mut self.progress }))
+    }
+
+    fn checkin_layer(&mut self) -> Result<(), AeError> {
+        self.layers = None;
+        if !std::mem::take(&mut self.input_checked_out) {
+            return Ok(());
+        }
+        if let Stage::SmartRender { extra, .. } = &self.stage {
+            extra.callbacks().checkin_layer_pixels(0)?;
+        }
+        Ok(())
+    }
+}
+
+struct AeProgress<'a>(&'a ae::InData);
+
+impl Progress for AeProgress<'_> {
+    type Error = AeError;
+
+    fn abort(&mut self) -> Result<(), AeError> {
+        Ok(self.0.abort()?)
+    }
+
+    fn progress(&mut self, done: usize, total: usize) -> Result<(), AeError> {
+        Ok(self.0.progress(done as i32, total as i32)?)
+    }
+}
+
+/// YUV encoding of a Premiere pixel format, `None` for the BGRA ones.
+fn yuv_format(format: ae::pr::PixelFormat) -> Option<YuvFormat> {
+    match format {
+        ae::pr::PixelFormat::Vuya4444_8u     => Some(YuvFormat::new(YuvMatrix::Rec601, YuvRange::Video)),
+        ae::pr::PixelFormat::Vuya4444_8u709  => Some(YuvFormat::new(YuvMatrix::Rec709, YuvRange::Video)),
+        ae::pr::PixelFormat::Vuya4444_32f    => Some(YuvFormat::new(YuvMatrix::Rec601, YuvRange::Full)),
+        ae::pr::PixelFormat::Vuya4444_32f709 => Some(YuvFormat::new(YuvMatrix::Rec709, YuvRange::Full)),
+        _ => None,
+    }
+}
+
+pub fn to_rect(rect: ae::Rect) -> Rect {
+    Rect::new(rect.left, rect.top, rect.right, rect.bottom)
+}
+
+pub fn from_rect(rect: Rect) -> ae::Rect {
+    ae::Rect { left: rect.left, top: rect.top, right: rect.right, bottom: rect.bottom }
+}
+
+fn pixels(depth: Depth, bytes: &[u8]) -> Pixels<'_> {
+    match depth {
+        Depth::U8 => Pixels::U8(bytes),
+        Depth::Ae16 => Pixels::Ae16(cast_slice(bytes)),
+        Depth::F32 => Pixels::F32(cast_slice(bytes)),
+    }
+}
+
+fn pixels_mut(depth: Depth, bytes: &mut [u8]) -> PixelsMut<'_> {
+    match depth {
+        Depth::U8 => PixelsMut::U8(bytes),
+        Depth::Ae16 => PixelsMut::Ae16(cast_slice_mut(bytes)),
+        Depth::F32 => PixelsMut::F32(cast_slice_mut(bytes)),
+    }
+}
+
+fn cast_slice<T>(bytes: &[u8]) -> &[T] {
+    // SAFETY: host worlds are aligned for their channel type and every bit pattern is a valid channel.
+    let (head, body, _) = unsafe { bytes.align_to::<T>() };
+    assert!(head.is_empty(), "misaligned world buffer");
+    body
+}
+
+fn cast_slice_mut<T>(bytes: &mut [u8]) -> &mut [T] {
+    // SAFETY: see `cast_slice`.
+    let (head, body, _) = unsafe { bytes.align_to_mut::<T>() };
+    assert!(head.is_empty(), "misaligned world buffer");
+    body
+}
@@ -1,34 +1,59 @@
+mod ae_host;
 
-#![feature(test)]
-extern crate test;
+use after_effects as ae;
+use ae_host::{AeError, AeHost, Regions, Stage};
+use unmult_core::host::Effect;
+use unmult_core::params::{ParamId, ParamKind, ParamValue, PARAMS};
+use unmult_core::sequence::SequenceData;
+use unmult_core::settings::{Background, Settings};
 
-mod generated_lut;
-use generated_lut::LUT;
+// Parameters are declared in `unmult_core::params`; the plugin only maps them onto AE controls.
+type Params = ParamId;
 
-use after_effects::{self as ae, sys::PF_Pixel};
-use rgba_to_yuv::RgbaPixel;
-
-mod rgba_to_yuv;
-
-#[derive(Eq, PartialEq, Hash, Clone, Copy, Debug)]
-enum Params {
+#[derive(Default)]
+struct Plugin {
+    effect: Effect,
 }
 
+/// Per-instance sequence data, flattened with the project.
 #[derive(Default)]
-struct Plugin { }
+struct Instance {
+    data: SequenceData,
+}
 
-ae::define_effect!(Plugin, (), Params);
+ae::define_effect!(Plugin, Instance, Params);
 
 impl AdobePluginGlobal for Plugin {
     fn can_load(_host_name: &str, _host_version: &str) -> bool {
         true
     }
 
-    fn params_setup(&self, _params: &mut ae::Parameters<Params>, _in_data: InData, _: OutData) -> Result<(), Error> {
+    fn params_setup(&self, params: &mut ae::Parameters<Params>, _in_data: InData, _: OutData) -> Result<(), Error> {
+        for def in PARAMS {
+            match def.kind {
+                ParamKind::Popup { options, default, .. } => params.add(def.id, def.name, ae::PopupDef::setup(|d| {
+                    d.set_options(options);
+                    d.set_default(default as i32 + 1);
+                }))?,
+                ParamKind::Percent { default } => params.add(def.id, def.name, ae::FloatSliderDef::setup(|f| {
+                    f.set_valid_min(0.0);
+                    f.set_valid_max(100.0);
+                    f.set_slider_min(0.0);
+                    f.set_slider_max(100.0);
+                    f.set_default(default as f64 * 100.0);
+                    f.set_value(f.default());
+                    f.set_precision(1);
+                    f.set_display_flags(ae::ValueDisplayFlag::PERCENT);
+                }))?,
+                ParamKind::Colour { default } => params.add(def.id, def.name, ae::ColorDef::setup(|c| {
+                    c.set_default(to_pixel8(default));
+                }))?,
+            }
+        }
         Ok(())
     }
 
-    fn handle_command(&mut self, cmd: ae::Command, in_data: InData, mut out_data: OutData, _params: &mut ae::Parameters<Params>) -> Result<(), ae::Error> {
+    fn handle_command(&mut self, cmd: ae::Command, in_data: InData, mut out_data: OutData, params: &mut ae::Parameters<Params>) -> Result<(), ae::Error> {
         match cmd {
             ae::Command::About => {
                 self.about(&mut out_data);
@@ -37,13 +62,49 @@ impl AdobePluginGlobal for Plugin {
                 self.global_setup(&in_data)?;
             }
             ae::Command::Render { in_layer, out_layer } => {
-                self.legacy_render(&in_data, in_layer, out_layer)?;
+                self.legacy_render(&in_data, in_layer, out_layer, params)?;
             }
             ae::Command::SmartPreRender { extra } => {
                 self.smart_pre_render(&in_data, extra)?;
             }
             ae::Command::SmartRender { extra } => {
-                self.smart_render(extra)?;
+                self.smart_render(&in_data, extra, params)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl AdobePluginInstance for Instance {
+    fn flatten(&self) -> Result<(u16, Vec<u8>), Error> {
+        Ok((SequenceData::VERSION, self.data.flatten()))
+    }
+
+    fn unflatten(version: u16, serialized: &[u8]) -> Result<Self, Error> {
+        // Corrupt data must not stop the project from opening; the instance starts over.
+        let data = SequenceData::unflatten(version, serialized).unwrap_or_else(|e| {
+            log::warn!("discarding version {version} sequence data: {e}");
+            SequenceData::default()
+        });
+        Ok(Self { data })
+    }
+
+    fn render(&self, _: &mut PluginState, _: &Layer, _: &mut Layer) -> Result<(), ae::Error> {
+        Ok(())
+    }
+
+    fn do_dialog(&mut self, _: &mut PluginState) -> Result<(), ae::Error> {
+        Ok(())
+    }
+
+    fn handle_command(&mut self, plugin: &mut PluginState, cmd: ae::Command) -> Result<(), ae::Error> {
+        match cmd {
+            // Setup, resetup, flatten and setdown are handled by `define_effect!` through
+            // `Default`, `unflatten`, `flatten` and `Drop`. Renders run on several threads at
+            // once and leave the data alone; the settings are kept from the UI thread instead.
+            ae::Command::UpdateParamsUi => {
+                self.data.settings = Plugin::settings(plugin.params)?;
             }
             _ => {}
         }
@@ -59,211 +120,57 @@ impl Plugin {
     fn global_setup(&mut self, in_data: &InData) -> Result<(), ae::Error> {
         win_dbg_logger::DEBUGGER_LOGGER.set_force_log_without_debugger(true);
         log::info!("GlobalSetup");
-        // For Premiere - declare supported pixel formats
         if in_data.is_premiere() {
-            let suite = ae::pf::suites::PixelFormat::new()?;
-
-            // Add the pixel formats we support in order of preference.
-            suite.clear_supported_pixel_formats(in_data.effect_ref())?;
-            let formats = [
-                ae::pr::PixelFormat::Bgra4444_8u,
-                ae::pr::PixelFormat::Bgra4444_16u,
-                ae::pr::PixelFormat::Bgra4444_32f,
-            ];
-            for x in formats {
-                suite.add_supported_pixel_format(in_data.effect_ref(), x)?;
-            }
+            ae::pf::suites::PixelFormat::new()?.clear_supported_pixel_formats(in_data.effect_ref())?;
         }
-        Ok(())
+        self.effect.global_setup(&mut AeHost::new(in_data, Stage::GlobalSetup)).map_err(|AeError(e)| e)
     }
 
-    fn legacy_render(&mut self, in_data: &InData, in_layer: ae::Layer, out_layer: ae::Layer) -> Result<(), ae::Error> {
+    fn legacy_render(&mut self, in_data: &InData, in_layer: ae::Layer, out_layer: ae::Layer, params: &ae::Parameters<Params>) -> Result<(), ae::Error> {
         if !in_data.is_premiere() {
             // We don't support non-SmartFX unless it's Premiere
             return Err(Error::BadCallbackParameter);
         }
 
-        self.do_render(in_layer, out_layer)?;
-    
+        let settings = Self::settings(params)?;
+        let mut host = AeHost::new(in_data, Stage::Render { in_layer, out_layer });
+        self.effect.render(&mut host, &settings).map_err(|AeError(e)| e)?;
         Ok(())
     }
 
-    fn smart_pre_render(&mut self, in_data: &InData, mut extra: ae::PreRenderExtra) -> Result<(), ae::Error> {
-        let req = extra.output_request();
-
-        if let Ok(in_result) = extra.callbacks().checkout_layer(0, 0, &req, in_data.current_time(), in_data.time_step(), in_data.time_scale()) {
-            let _ = extra.union_result_rect(in_result.result_rect.into());
-            let _ = extra.union_max_result_rect(in_result.max_result_rect.into());
-        }
+    fn smart_pre_render(&mut self, in_data: &InData, extra: ae::PreRenderExtra) -> Result<(), ae::Error> {
+        self.effect.smart_pre_render(&mut AeHost::new(in_data, Stage::PreRender(extra))).map_err(|AeError(e)| e)?;
         Ok(())
     }
 
-    fn smart_render(&mut self, extra: ae::SmartRenderExtra) -> Result<(), ae::Error> {
-        let cb = extra.callbacks();
-        let Some(input_world) = cb.checkout_layer_pixels(0)? else {
+    fn smart_render(&mut self, in_data: &InData, extra: ae::SmartRenderExtra, params: &ae::Parameters<Params>) -> Result<(), ae::Error> {
+        let Some(regions) = extra.pre_render_data::<Regions>().copied() else {
             return Ok(());
         };
-
-        if let Ok(Some(output_world)) = cb.checkout_output() {
-            self.do_render(input_world, output_world)?;
-        }
-
-        cb.checkin_layer_pixels(0)?;
+        let settings = Self::settings(params)?;
+        let mut host = AeHost::new(in_data, Stage::SmartRender { extra, regions });
+        self.effect.smart_render(&mut host, &settings).map_err(|AeError(e)| e)?;
         Ok(())
     }
 
-    fn do_render(&self, in_layer: ae::Layer, mut out_layer: ae::Layer) -> Result<(), Error> {
-        let progress_final = out_layer.height() as _;
-        in_layer.iterate_with(&mut out_layer, 0, progress_final, None, |_x: i32, _y: i32, pixel: ae::GenericPixel, out_pixel: ae::GenericPixelMut| -> Result<(), Error> {
-            match (pixel, out_pixel) {
-                (ae::GenericPixel::Pixel8(pixel), ae::GenericPixelMut::Pixel8(out_pixel)) => {
-                    inner_render(pixel, out_pixel);
-                }
-                (ae::GenericPixel::Pixel16(pixel), ae::GenericPixelMut::Pixel16(out_pixel)) => {
-                    let new_pixel = RgbaPixel::new(pixel.red, pixel.green, pixel.blue, pixel.alpha).unmult_rgba();
-                    out_pixel.alpha = new_pixel.get_alpha();
-                    out_pixel.red   = new_pixel.get_red();
-                    out_pixel.green = new_pixel.get_green();
-                    out_pixel.blue  = new_pixel.get_blue();
-                }
-                (ae::GenericPixel::PixelF32(pixel), ae::GenericPixelMut::PixelF32(out_pixel)) => {
-                    let new_pixel = RgbaPixel::new(pixel.red, pixel.green, pixel.blue, pixel.alpha).unmult_rgba();
-                    out_pixel.alpha = new_pixel.get_alpha();
-                    out_pixel.red   = new_pixel.get_red();
-                    out_pixel.green = new_pixel.get_green();
-                    out_pixel.blue  = new_pixel.get_blue();
-                }
-                _ => return Err(Error::BadCallbackParameter)
-            }
-
-            Ok(())
-        })?;
-        Ok(())
+    fn settings(params: &ae::Parameters<Params>) -> Result<Settings, Error> {
+        Settings::from_params(|def| {
+            let param = params.get(def.id)?;
+            Ok(match def.kind {
+                // Popup values are 1-based.
+                ParamKind::Popup { .. } => ParamValue::Popup((param.as_popup()?.value() as usize).wrapping_sub(1)),
+                ParamKind::Percent { .. } => ParamValue::Percent((param.as_float_slider()?.value() / 100.0) as f32),
+                ParamKind::Colour { .. } => ParamValue::Colour(from_pixel8(param.as_color()?.value())),
+            })
+        })
     }
 }
 
-pub fn inner_render(pixel: &PF_Pixel, out_pixel: &mut PF_Pixel) {
-    let a = pixel.alpha;
-    let r = pixel.red;
-    let g = pixel.green;
-    let b = pixel.blue;
-
-    let max_rgb = r.max(g).max(b);
-    let offset = (max_rgb as usize) << 8;
-
-    let a = (((a as usize) * max_rgb as usize) >> 8) as u8;
-    out_pixel.alpha = a;
-    out_pixel.red   = LUT[offset + r as usize];
-    out_pixel.green = LUT[offset + g as usize];
-    out_pixel.blue  = LUT[offset + b as usize];
+fn to_pixel8(colour: Background) -> ae::Pixel8 {
+    let channel = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+    ae::Pixel8 { alpha: 255, red: channel(colour.red), green: channel(colour.green), blue: channel(colour.blue) }
 }
 
-pub fn inner_render_2(pixel: &PF_Pixel, out_pixel: &mut PF_Pixel) {
-    let a = pixel.alpha;
-    let r = pixel.red;
-    let g = pixel.green;
-    let b = pixel.blue;
-
-    let max_rgb = r.max(g).max(b);
-    let offset = (max_rgb as usize) << 8;
-
-    let a = (((a as usize) * max_rgb as usize) >> 8) as u8;
-    out_pixel.alpha = a;
-    out_pixel.red   = LUT[offset + r as usize];
-    out_pixel.green = LUT[offset + g as usize];
-    out_pixel.blue  = LUT[offset + b as usize];
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use test::Bencher;
-
-    #[test]
-    fn test_inner_render_same_values() {
-        let input_pixel = PF_Pixel { red: 0xFF, green: 0, blue: 0, alpha: 0x88 };
-        let mut output_pixel = PF_Pixel { red: 0, green: 0, blue: 0, alpha: 0 };
-        inner_render(&input_pixel, &mut output_pixel);
-        
-        let input_pixel_2 = PF_Pixel { red: 0xFF, green: 0, blue: 0, alpha: 0x88 };
-        let mut output_pixel_2 = PF_Pixel { red: 0, green: 0, blue: 0, alpha: 0 };
-        inner_render_2(&input_pixel_2, &mut output_pixel_2);
-
-        assert_eq!(output_pixel.alpha, output_pixel_2.alpha);
-        assert_eq!(output_pixel.red, output_pixel_2.red);
-        assert_eq!(output_pixel.green, output_pixel_2.green);
-        assert_eq!(output_pixel.blue, output_pixel_2.blue);
-    }
-
-    #[bench]
-    fn bench_inner_render_jpg(b: &mut Bencher) {
-        let img = image::open("./4k.jpg").unwrap();
-        let input_pixels = img.to_rgba8().into_raw();
-        let input_pixels = input_pixels.chunks_exact(4).map(|chunk| PF_Pixel {
-            red: chunk[0],
-            green: chunk[1],
-            blue: chunk[2],
-            alpha: chunk[3],
-        }).collect::<Vec<_>>();
-        let mut output_pixels = vec![PF_Pixel { red: 0, green: 0, blue: 0, alpha: 0 }; 3840 * 2160];
-        b.iter(|| {
-            for (input_pixel, output_pixel) in input_pixels.iter().zip(output_pixels.iter_mut()) {
-                inner_render(input_pixel, output_pixel);
-            }
-        });
-    }
-
-    #[bench]
-    fn bench_inner_render_2_jpg(b: &mut Bencher) {
-        let img = image::open("./4k.jpg").unwrap();
-        let input_pixels = img.to_rgba8().into_raw();
-        let input_pixels = input_pixels.chunks_exact(4).map(|chunk| PF_Pixel {
-            red: chunk[0],
-            green: chunk[1],
-            blue: chunk[2],
-            alpha: chunk[3],
-        }).collect::<Vec<_>>();
-        let mut output_pixels = vec![PF_Pixel { red: 0, green: 0, blue: 0, alpha: 0 }; 3840 * 2160];
-        b.iter(|| {
-            for (input_pixel, output_pixel) in input_pixels.iter().zip(output_pixels.iter_mut()) {
-                inner_render_2(input_pixel, output_pixel);
-            }
-        });
-    }
-
-    #[bench]
-    fn bench_inner_render_png(b: &mut Bencher) {
-        let img = image::open("./4k.png").unwrap();
-        let input_pixels = img.to_rgba8().into_raw();
-        let input_pixels = input_pixels.chunks_exact(4).map(|chunk| PF_Pixel {
-            red: chunk[0],
-            green: chunk[1],
-            blue: chunk[2],
-            alpha: chunk[3],
-        }).collect::<Vec<_>>();
-        let mut output_pixels = vec![PF_Pixel { red: 0, green: 0, blue: 0, alpha: 0 }; 3840 * 3840];
-        b.iter(|| {
-            for (input_pixel, output_pixel) in input_pixels.iter().zip(output_pixels.iter_mut()) {
-                inner_render(input_pixel, output_pixel);
-            }
-        });
-    }
-
-    #[bench]
-    fn bench_inner_render_2_png(b: &mut Bencher) {
-        let img = image::open("./4k.png").unwrap();
-        let input_pixels = img.to_rgba8().into_raw();
-        let input_pixels = input_pixels.chunks_exact(4).map(|chunk| PF_Pixel {
-            red: chunk[0],
-            green: chunk[1],
-            blue: chunk[2],
-            alpha: chunk[3],
-        }).collect::<Vec<_>>();
-        let mut output_pixels = vec![PF_Pixel { red: 0, green: 0, blue: 0, alpha: 0 }; 3840 * 3840];
-        b.iter(|| {
-            for (input_pixel, output_pixel) in input_pixels.iter().zip(output_pixels.iter_mut()) {
-                inner_render_2(input_pixel, output_pixel);
-            }
-        });
-    }
+fn from_pixel8(pixel: ae::Pixel8) -> Background {
+    Background::new(pixel.red as f32 / 255.0, pixel.green as f32 / 255.0, pixel.blue as f32 / 255.0)
 }
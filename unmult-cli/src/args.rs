@@ -0,0 +1,352 @@
+//! Command-line parsing. Effect options are generated from [`PARAMS`], so every control of
+//! the plugin has a flag of the same name.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+use exr::compression::Compression;
+use unmult_core::params::{ParamDef, ParamKind, ParamValue, PARAMS};
+use unmult_core::settings::{Background, Settings};
+
+use crate::frames::Pattern;
+
+/// Channel depth of the written file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputDepth {
+    U8,
+    U16,
+    F32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub settings: Settings,
+    /// Depth of the output, `None` to keep the input's where the format allows.
+    pub depth: Option<OutputDepth>,
+    /// Compression of EXR outputs, `None` to keep the input's.
+    pub compression: Option<Compression>,
+    /// Worker threads, `None` for one per core.
+    pub threads: Option<usize>,
+    /// Frames of a sequence to process, `None` for every input frame on disk.
+    pub frames: Option<Vec<RangeInclusive<u32>>>,
+    /// Re-process sequence frames whose output is already up to date.
+    pub overwrite: bool,
+}
+
+impl Options {
+    /// Text naming every option that changes what is written, so a sequence is redone when
+    /// any of them differ from the last run.
+    pub fn fingerprint(&self) -> String {
+        format!("unmult {}\n{:?}\n{:?}\n{:?}\n", env!("CARGO_PKG_VERSION"), self.settings, self.depth, self.compression)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Run(Options),
+    Help,
+    Version,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgError {
+    UnknownOption(String),
+    MissingValue(String),
+    InvalidValue { option: String, value: String, expected: String },
+    /// Wrong number of positional arguments.
+    Positional(usize),
+    /// Only one of the input and output is a frame pattern.
+    PatternMismatch,
+    /// `--frames` without a frame pattern input.
+    FramesWithoutPattern,
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgError::UnknownOption(o) => write!(f, "unknown option '{o}'"),
+            ArgError::MissingValue(o) => write!(f, "option '{o}' needs a value"),
+            ArgError::InvalidValue { option, value, expected } => write!(f, "invalid value '{value}' for '{option}', expected {expected}"),
+            ArgError::Positional(n) => write!(f, "expected an input and an output path, got {n} path(s)"),
+            ArgError::PatternMismatch => write!(f, "input and output must both be frame patterns, or both single files"),
+            ArgError::FramesWithoutPattern => write!(f, "'--frames' needs a frame pattern such as fire_####.png"),
+        }
+    }
+}
+
+impl std::error::Error for ArgError {}
+
+/// Flag of a parameter: its name in lowercase with dashes, e.g. `--alpha-source`.
+pub fn flag(def: &ParamDef) -> String {
+    format!("--{}", def.name.to_lowercase().replace(' ', "-"))
+}
+
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, ArgError> {
+    let mut settings = Settings::default();
+    let (mut depth, mut compression, mut threads, mut frames, mut overwrite) = (None, None, None, None, false);
+    let mut paths: Vec<PathBuf> = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            paths.extend(args.by_ref().map(PathBuf::from));
+            break;
+        }
+        if !arg.starts_with('-') || arg == "-" {
+            paths.push(PathBuf::from(arg));
+            continue;
+        }
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "--overwrite" => {
+                overwrite = true;
+                continue;
+            }
+            _ => {}
+        }
+        let (option, inline) = match arg.split_once('=') {
+            Some((option, value)) => (option.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        let mut value = || inline.clone().or_else(|| args.next()).ok_or_else(|| ArgError::MissingValue(option.clone()));
+        match option.as_str() {
+            "--depth" => depth = Some(parse_depth(&option, &value()?)?),
+            "--compression" => {
+                let v = value()?;
+                let names = COMPRESSIONS.map(|(name, _)| name);
+                compression = Some(COMPRESSIONS.iter().find(|(name, _)| name.eq_ignore_ascii_case(&v)).map(|&(_, c)| c).ok_or_else(|| invalid(&option, &v, &format!("one of {}", names.join(", "))))?);
+            }
+            "--threads" => {
+                let v = value()?;
+                threads = Some(v.parse().map_err(|_| invalid(&option, &v, "a number of threads"))?);
+            }
+            "--frames" => {
+                let v = value()?;
+                frames = Some(parse_frames(&v).ok_or_else(|| invalid(&option, &v, "frames such as 1-100 or 1,5,10-20"))?);
+            }
+            _ => {
+                let def = PARAMS.iter().find(|def| flag(def) == option).ok_or_else(|| ArgError::UnknownOption(option.clone()))?;
+                settings.set(def.id, parse_value(def, &option, &value()?)?);
+            }
+        }
+    }
+    let [input, output] = <[PathBuf; 2]>::try_from(paths).map_err(|paths| ArgError::Positional(paths.len()))?;
+    match (Pattern::parse(&input).is_some(), Pattern::parse(&output).is_some()) {
+        (true, true) => {}
+        (false, false) if frames.is_none() => {}
+        (false, false) => return Err(ArgError::FramesWithoutPattern),
+        _ => return Err(ArgError::PatternMismatch),
+    }
+    Ok(Command::Run(Options { input, output, settings, depth, compression, threads, frames, overwrite }))
+}
+
+/// EXR compressions by `--compression` name.
+const COMPRESSIONS: [(&str, Compression); 10] = [
+    ("none", Compression::Uncompressed),
+    ("rle", Compression::RLE),
+    ("zips", Compression::ZIP1),
+    ("zip", Compression::ZIP16),
+    ("piz", Compression::PIZ),
+    ("pxr24", Compression::PXR24),
+    ("b44", Compression::B44),
+    ("b44a", Compression::B44A),
+    ("dwaa", Compression::DWAA(None)),
+    ("dwab", Compression::DWAB(None)),
+];
+
+fn parse_depth(option: &str, value: &str) -> Result<OutputDepth, ArgError> {
+    match value {
+        "8" => Ok(OutputDepth::U8),
+        "16" => Ok(OutputDepth::U16),
+        "32" => Ok(OutputDepth::F32),
+        _ => Err(invalid(option, value, "8, 16 or 32")),
+    }
+}
+
+fn parse_value(def: &ParamDef, option: &str, value: &str) -> Result<ParamValue, ArgError> {
+    match def.kind {
+        ParamKind::Popup { options, keys, .. } => keys
+            .iter()
+            .zip(options)
+            .position(|(key, label)| key.eq_ignore_ascii_case(value) || label.eq_ignore_ascii_case(value))
+            .map(ParamValue::Popup)
+            .ok_or_else(|| invalid(option, value, &format!("one of {}", keys.join(", ")))),
+        ParamKind::Percent { .. } => parse_fraction(value).map(ParamValue::Percent).ok_or_else(|| invalid(option, value, "a fraction such as 0.25 or 25%")),
+        ParamKind::Colour { .. } => parse_colour(value).map(ParamValue::Colour).ok_or_else(|| invalid(option, value, "#rrggbb or r,g,b in 0..1")),
+    }
+}
+
+/// Comma-separated frames and inclusive ranges: `1-100`, `1,5,10-20`.
+fn parse_frames(value: &str) -> Option<Vec<RangeInclusive<u32>>> {
+    value
+        .split(',')
+        .map(|part| {
+            let (start, end) = part.split_once('-').unwrap_or((part, part));
+            let (start, end) = (start.trim().parse().ok()?, end.trim().parse().ok()?);
+            (start <= end).then_some(start..=end)
+        })
+        .collect()
+}
+
+/// `0.25` or `25%`.
+fn parse_fraction(value: &str) -> Option<f32> {
+    let v = match value.strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f32>().ok()? / 100.0,
+        None => value.parse::<f32>().ok()?,
+    };
+    v.is_finite().then_some(v)
+}
+
+/// `#rrggbb` or `r,g,b` with components in `0..1`.
+fn parse_colour(value: &str) -> Option<Background> {
+    if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok().map(|v| v as f32 / 255.0);
+        return Some(Background::new(channel(0)?, channel(2)?, channel(4)?));
+    }
+    let parts: Vec<f32> = value.split(',').map(|p| p.trim().parse::<f32>().ok().filter(|v| v.is_finite())).collect::<Option<_>>()?;
+    let [r, g, b] = parts[..] else {
+        return None;
+    };
+    Some(Background::new(r, g, b))
+}
+
+fn invalid(option: &str, value: &str, expected: &str) -> ArgError {
+    ArgError::InvalidValue { option: option.to_string(), value: value.to_string(), expected: expected.to_string() }
+}
+
+/// Usage text listing every option and its values.
+pub fn usage() -> String {
+    let mut out = String::from("Usage: unmult [OPTIONS] <INPUT> <OUTPUT>\n\n");
+    out += "Reads PNG, TIFF, JPEG or OpenEXR and writes PNG, TIFF or OpenEXR, chosen by extension.\n";
+    out += "EXR outputs keep the input's other layers, channels and attributes.\n";
+    out += "A frame pattern such as fire_####.png or fire_%04d.png processes a numbered sequence;\n";
+    out += "the output must then be a pattern too. Frames whose output is newer than the input and\n";
+    out += "was written with the same options, recorded in a hidden .unmult file, are skipped.\n";
+    out += "\nEffect options:\n";
+    for def in PARAMS {
+        let values = match def.kind {
+            ParamKind::Popup { keys, default, .. } => format!("{} [default: {}]", keys.join("|"), keys[default]),
+            ParamKind::Percent { default } => format!("<fraction> [default: {default}]"),
+            ParamKind::Colour { .. } => "<#rrggbb|r,g,b> [default: #000000]".to_string(),
+        };
+        out += &format!("  {:<18} {}\n", flag(def), values);
+    }
+    out += "\nOutput options:\n";
+    out += "  --depth            8|16|32, EXR writes 8 and 16 as half [default: the input's]\n";
+    out += &format!("  --compression      {}, for EXR [default: the input's, else zip]\n", COMPRESSIONS.map(|(name, _)| name).join("|"));
+    out += "  --threads          <n> [default: one per core]\n";
+    out += "\nSequence options:\n";
+    out += "  --frames           <list>, e.g. 1-100 or 1,5,10-20 [default: every input frame]\n";
+    out += "  --overwrite        Re-process frames whose output is up to date\n";
+    out += "\n";
+    out += "  -h, --help         Print this help\n";
+    out += "  -V, --version      Print the version\n";
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use unmult_core::settings::{AlphaSource, Association, Levels, Mode, Transfer};
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    fn run(s: &str) -> Options {
+        match parse(args(s)).unwrap() {
+            Command::Run(options) => options,
+            other => panic!("{other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_defaults() {
+        let options = run("in.png out.png");
+        assert_eq!(options.input, PathBuf::from("in.png"));
+        assert_eq!(options.output, PathBuf::from("out.png"));
+        assert_eq!(options.settings, Settings::default());
+        assert_eq!((options.depth, options.compression, options.threads), (None, None, None));
+        assert_eq!((options.frames, options.overwrite), (None, false));
+    }
+
+    #[test]
+    fn test_effect_options() {
+        let options = run("--mode unscreen --alpha-source=rec709 --black-point 10% --white-point 0.9 --output Premultiplied \
+                           --input-transfer srgb --background #ff8000 --view-colour 0,0.5,1 in.tif out.tif");
+        let expected = Settings::new(Mode::Unscreen)
+            .with_alpha_source(AlphaSource::Rec709)
+            .with_levels(Levels::new(0.1, 0.9))
+            .with_association(Association::Premultiplied)
+            .with_transfer(Transfer::Srgb)
+            .with_background(Background::new(1.0, 128.0 / 255.0, 0.0))
+            .with_view_colour(Background::new(0.0, 0.5, 1.0));
+        assert_eq!(options.settings, expected);
+    }
+
+    #[test]
+    fn test_every_param_has_a_flag() {
+        let text = usage();
+        for def in PARAMS {
+            assert!(text.contains(&flag(def)), "{}", def.name);
+            let value = match def.kind {
+                ParamKind::Popup { keys, .. } => keys[keys.len() - 1].to_string(),
+                ParamKind::Percent { .. } => "0.5".to_string(),
+                ParamKind::Colour { .. } => "#102030".to_string(),
+            };
+            let options = run(&format!("{} {value} a b", flag(def)));
+            assert_ne!(options.settings, Settings::default(), "{}", def.name);
+        }
+    }
+
+    #[test]
+    fn test_output_options() {
+        let options = run("--depth 16 --compression PIZ --threads=3 -- -in.png out.png");
+        assert_eq!(options.depth, Some(OutputDepth::U16));
+        assert_eq!(options.compression, Some(Compression::PIZ));
+        assert_eq!(options.threads, Some(3));
+        assert_eq!(options.input, PathBuf::from("-in.png"));
+        assert_eq!(parse(args("-h")), Ok(Command::Help));
+        assert_eq!(parse(args("a --version")), Ok(Command::Version));
+    }
+
+    #[test]
+    fn test_sequence_options() {
+        let options = run("--frames 1-10,12 --overwrite fire_####.png out/fire_%04d.tif");
+        assert_eq!(options.frames, Some(vec![1..=10, 12..=12]));
+        assert!(options.overwrite);
+        assert_eq!(run("a_#.png b_#.png").frames, None);
+
+        // Only options that change the output count as a change.
+        let fingerprint = |line: &str| run(line).fingerprint();
+        assert_eq!(fingerprint("a_#.png b_#.png"), fingerprint("--overwrite --threads 2 --frames 1-3 a_#.png b_#.png"));
+        for other in ["--mode unscreen", "--depth 16", "--compression piz"] {
+            assert_ne!(fingerprint("a_#.png b_#.png"), fingerprint(&format!("{other} a_#.png b_#.png")), "{other}");
+        }
+
+        assert!(matches!(parse(args("--frames 10-1 a_#.png b_#.png")), Err(ArgError::InvalidValue { .. })));
+        assert!(matches!(parse(args("--frames 1-x a_#.png b_#.png")), Err(ArgError::InvalidValue { .. })));
+        assert_eq!(parse(args("a_####.png b.png")), Err(ArgError::PatternMismatch));
+        assert_eq!(parse(args("a.png b_####.png")), Err(ArgError::PatternMismatch));
+        assert_eq!(parse(args("--frames 1-5 a.png b.png")), Err(ArgError::FramesWithoutPattern));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(parse(args("--nope a b")), Err(ArgError::UnknownOption("--nope".into())));
+        assert_eq!(parse(args("a b --mode")), Err(ArgError::MissingValue("--mode".into())));
+        assert!(matches!(parse(args("--mode sideways a b")), Err(ArgError::InvalidValue { .. })));
+        assert!(matches!(parse(args("--black-point lots a b")), Err(ArgError::InvalidValue { .. })));
+        assert!(matches!(parse(args("--background #12345 a b")), Err(ArgError::InvalidValue { .. })));
+        assert!(matches!(parse(args("--depth 12 a b")), Err(ArgError::InvalidValue { .. })));
+        assert!(matches!(parse(args("--compression lzw a b")), Err(ArgError::InvalidValue { .. })));
+        assert_eq!(parse(args("a")), Err(ArgError::Positional(1)));
+        assert_eq!(parse(args("a b c")), Err(ArgError::Positional(3)));
+    }
+}
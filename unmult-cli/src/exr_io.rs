@@ -0,0 +1,193 @@
+//! OpenEXR files. The R, G, B and A channels of the first layer that has them are processed
+//! as `f32`; every other layer, channel and attribute is written back as it was read.
+
+use std::path::Path;
+
+use exr::compression::Compression;
+use exr::image::{AnyChannel, AnyChannels, Encoding, FlatImage, FlatSamples, Layer};
+use exr::math::Vec2;
+use exr::meta::attribute::IntegerBounds;
+use exr::meta::header::{ImageAttributes, LayerAttributes};
+use exr::prelude::{f16, WritableImage};
+
+use crate::args::OutputDepth;
+use crate::image_io::{Image, Pixels};
+
+const RGBA: [&str; 4] = ["R", "G", "B", "A"];
+
+/// The EXR an image was read from, kept so its header and other channels survive the round trip.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExrSource {
+    image: FlatImage,
+    /// Layer holding the processed channels.
+    layer: usize,
+}
+
+/// Reads every layer at full resolution. `Ok(None)` if no layer has R, G and B channels.
+pub fn read(path: &Path) -> exr::error::Result<Option<Image>> {
+    let image = exr::image::read::read_all_flat_layers_from_file(path)?;
+    let Some((layer, channels)) = image.layer_data.iter().enumerate().find_map(|(i, layer)| Some((i, rgba_channels(&layer.channel_data)?))) else {
+        return Ok(None);
+    };
+    let data = &image.layer_data[layer];
+    let (width, height) = (data.size.width(), data.size.height());
+    // A missing alpha is opaque.
+    let mut pixels = vec![1.0; width * height * 4];
+    for (c, index) in channels.iter().enumerate() {
+        if let Some(index) = index {
+            let samples = data.channel_data.list[*index].sample_data.values_as_f32();
+            pixels[c..].iter_mut().step_by(4).zip(samples).for_each(|(dst, v)| *dst = v);
+        }
+    }
+    Ok(Some(Image { width, height, pixels: Pixels::F32(pixels), exr: Some(ExrSource { image, layer }) }))
+}
+
+/// Indices of the R, G, B and, if there is one, A channel. Subsampled channels don't count.
+fn rgba_channels(channels: &AnyChannels<FlatSamples>) -> Option<[Option<usize>; 4]> {
+    let found = RGBA.map(|name| channels.list.iter().position(|c| c.name == *name && c.sampling == Vec2(1, 1)));
+    found[..3].iter().all(Option::is_some).then_some(found)
+}
+
+/// Writes `image` back into the EXR it was read from, or into a new single-layer file.
+/// Channels are half for 8 and 16-bit `depth`, float for 32, and otherwise keep the type they
+/// were read with. New files are half unless the image is float. `compression` replaces the
+/// source's, which defaults to ZIP for new files.
+pub fn write(image: Image, path: &Path, depth: Option<OutputDepth>, compression: Option<Compression>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let Pixels::F32(rgba) = image.convert(OutputDepth::F32)? else {
+        unreachable!("converted to float")
+    };
+    let is_float = matches!(image.pixels, Pixels::F32(_));
+    let (width, height) = (image.width, image.height);
+    let (mut exr, layer) = match image.exr {
+        Some(source) => (source.image, source.layer),
+        None => {
+            let layer = Layer::new((width, height), LayerAttributes::default(), Encoding::SMALL_LOSSLESS, AnyChannels::sort(Default::default()));
+            let bounds = IntegerBounds::from_dimensions((width, height));
+            (FlatImage::from_layers(ImageAttributes::new(bounds), vec![layer]), 0)
+        }
+    };
+
+    let layer = &mut exr.layer_data[layer];
+    if let Some(compression) = compression {
+        layer.encoding.compression = compression;
+    }
+    let list = &mut layer.channel_data.list;
+    let fallback = match list.iter().find(|c| c.name == *"R").map(|c| &c.sample_data) {
+        Some(FlatSamples::F16(_)) => SampleType::F16,
+        Some(_) => SampleType::F32,
+        None if is_float => SampleType::F32,
+        None => SampleType::F16,
+    };
+    for (c, name) in RGBA.iter().enumerate() {
+        let existing = list.iter().position(|channel| channel.name == **name);
+        let sample_type = match (depth, existing.map(|i| &list[i].sample_data)) {
+            (Some(OutputDepth::F32), _) => SampleType::F32,
+            (Some(OutputDepth::U8 | OutputDepth::U16), _) => SampleType::F16,
+            (None, Some(FlatSamples::F16(_))) => SampleType::F16,
+            (None, Some(_)) => SampleType::F32,
+            (None, None) => fallback,
+        };
+        let values = rgba[c..].iter().step_by(4).copied();
+        let samples = match sample_type {
+            SampleType::F16 => FlatSamples::F16(values.map(f16::from_f32).collect()),
+            SampleType::F32 => FlatSamples::F32(values.collect()),
+        };
+        match existing {
+            Some(i) => list[i].sample_data = samples,
+            None => list.push(AnyChannel::new(*name, samples)),
+        }
+    }
+    layer.channel_data = AnyChannels::sort(std::mem::take(list));
+
+    exr.write().to_file(path)?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy)]
+enum SampleType {
+    F16,
+    F32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TempDir;
+    use exr::image::Blocks;
+    use exr::meta::attribute::{AttributeValue, Text};
+
+    /// 4×2 half RGBA layer plus a float depth channel, tiled and PIZ compressed, with a
+    /// custom attribute.
+    fn source(path: &Path) {
+        let half = |values: [f32; 8]| FlatSamples::F16(values.map(f16::from_f32).to_vec());
+        let channels = AnyChannels::sort(
+            vec![
+                AnyChannel::new("R", half([0.0, 0.25, 0.5, 1.0, 2.0, 4.0, 0.1, 0.0])),
+                AnyChannel::new("G", half([0.0, 0.25, 0.25, 0.5, 1.0, 0.5, 0.2, 0.0])),
+                AnyChannel::new("B", half([0.0, 0.125, 0.5, 0.25, 0.5, 0.25, 0.3, 0.0])),
+                AnyChannel::new("A", half([1.0; 8])),
+                AnyChannel::new("Z", FlatSamples::F32(vec![7.0; 8])),
+            ]
+            .into(),
+        );
+        let encoding = Encoding { compression: Compression::PIZ, blocks: Blocks::Tiles(Vec2(16, 16)), ..Encoding::default() };
+        let mut image = FlatImage::from_layers(ImageAttributes::new(IntegerBounds::from_dimensions((4, 2))), vec![Layer::new((4, 2), LayerAttributes::default(), encoding, channels)]);
+        image.layer_data[0].attributes.other.insert(Text::from("shot"), AttributeValue::Text(Text::from("fx_010")));
+        image.write().to_file(path).unwrap();
+    }
+
+    fn samples<'a>(image: &'a FlatImage, name: &str) -> &'a FlatSamples {
+        &image.layer_data[0].channel_data.list.iter().find(|c| c.name == *name).unwrap().sample_data
+    }
+
+    #[test]
+    fn test_round_trip_keeps_header_and_channels() {
+        let dir = TempDir::new("exr_round_trip_keeps_header");
+        let (src, dst) = (dir.join("source.exr"), dir.join("copy.exr"));
+        source(&src);
+        let image = read(&src).unwrap().unwrap();
+        let Pixels::F32(pixels) = &image.pixels else { panic!("expected float pixels") };
+        assert_eq!(&pixels[16..20], [2.0, 1.0, 0.5, 1.0]);
+        write(image, &dst, None, None).unwrap();
+
+        let (before, after) = (exr::image::read::read_all_flat_layers_from_file(&src).unwrap(), exr::image::read::read_all_flat_layers_from_file(&dst).unwrap());
+        assert_eq!(before, after);
+        assert_eq!(after.layer_data[0].encoding.blocks, Blocks::Tiles(Vec2(16, 16)));
+        assert_eq!(after.layer_data[0].attributes.other.get(&Text::from("shot")), Some(&AttributeValue::Text(Text::from("fx_010"))));
+    }
+
+    #[test]
+    fn test_depth_and_compression() {
+        let dir = TempDir::new("exr_depth_and_compression");
+        let (src, dst) = (dir.join("depth.exr"), dir.join("depth_float.exr"));
+        source(&src);
+        write(read(&src).unwrap().unwrap(), &dst, Some(OutputDepth::F32), Some(Compression::ZIP16)).unwrap();
+        let out = exr::image::read::read_all_flat_layers_from_file(&dst).unwrap();
+        assert!(matches!(samples(&out, "R"), FlatSamples::F32(_)));
+        assert_eq!(samples(&out, "Z"), &FlatSamples::F32(vec![7.0; 8]));
+        assert_eq!(out.layer_data[0].encoding.compression, Compression::ZIP16);
+    }
+
+    #[test]
+    fn test_new_file() {
+        let dir = TempDir::new("exr_new_file");
+        let path = dir.join("new.exr");
+        let image = Image { width: 2, height: 1, pixels: Pixels::U8(vec![255, 0, 0, 255, 0, 0, 0, 0]), exr: None };
+        write(image, &path, None, None).unwrap();
+        let out = exr::image::read::read_all_flat_layers_from_file(&path).unwrap();
+        assert_eq!(samples(&out, "R"), &FlatSamples::F16(vec![f16::ONE, f16::ZERO]));
+        assert_eq!(samples(&out, "A"), &FlatSamples::F16(vec![f16::ONE, f16::ZERO]));
+        assert_eq!(out.layer_data[0].encoding.compression, Compression::ZIP16);
+    }
+
+    #[test]
+    fn test_missing_alpha_is_opaque() {
+        let dir = TempDir::new("exr_missing_alpha");
+        let path = dir.join("rgb.exr");
+        let channels = ["B", "G", "R"].map(|name| AnyChannel::new(name, FlatSamples::F32(vec![0.5; 2])));
+        let image = FlatImage::from_layers(ImageAttributes::new(IntegerBounds::from_dimensions((2, 1))), vec![Layer::new((2, 1), LayerAttributes::default(), Encoding::UNCOMPRESSED, AnyChannels::sort(channels.to_vec().into()))]);
+        image.write().to_file(&path).unwrap();
+        let Pixels::F32(pixels) = read(&path).unwrap().unwrap().pixels else { panic!("expected float pixels") };
+        assert_eq!(pixels, [0.5, 0.5, 0.5, 1.0, 0.5, 0.5, 0.5, 1.0]);
+    }
+}
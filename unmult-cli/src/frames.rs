@@ -0,0 +1,287 @@
+//! Numbered image sequences, named by a pattern such as `fire_####.png` or `fire_%04d.png`.
+
+use std::fmt;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+
+use crate::image_io::ImageError;
+
+/// A file name with a run of `#` or a `%0Nd` standing for a zero-padded frame number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    dir: PathBuf,
+    prefix: String,
+    suffix: String,
+    width: usize,
+}
+
+impl Pattern {
+    /// `None` if the file name has no frame number in it.
+    pub fn parse(path: &Path) -> Option<Pattern> {
+        let name = path.file_name()?.to_str()?;
+        let (start, len, width) = if let Some(start) = name.find('#') {
+            let len = name[start..].bytes().take_while(|&b| b == b'#').count();
+            (start, len, len)
+        } else {
+            let start = name.find('%')?;
+            let spec = &name[start + 1..];
+            let digits = spec.bytes().take_while(u8::is_ascii_digit).count();
+            if spec.as_bytes().get(digits) != Some(&b'd') || (digits > 0 && !spec.starts_with('0')) {
+                return None;
+            }
+            let width = if digits > 0 { spec[..digits].parse().ok()? } else { 1 };
+            (start, digits + 2, width)
+        };
+        Some(Pattern {
+            dir: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            prefix: name[..start].to_string(),
+            suffix: name[start + len..].to_string(),
+            width,
+        })
+    }
+
+    pub fn path(&self, frame: u32) -> PathBuf {
+        self.dir.join(format!("{}{:0width$}{}", self.prefix, frame, self.suffix, width = self.width))
+    }
+
+    /// Hidden file next to the frames recording the options they were written with.
+    pub fn manifest(&self) -> PathBuf {
+        self.dir.join(format!(".{}{}{}.unmult", self.prefix, "#".repeat(self.width), self.suffix))
+    }
+
+    /// Frame numbers of the files matching the pattern, in order.
+    pub fn scan(&self) -> io::Result<Vec<u32>> {
+        let dir = if self.dir.as_os_str().is_empty() { Path::new(".") } else { &self.dir };
+        let mut frames = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let name = entry?.file_name();
+            let Some(digits) = name.to_str().and_then(|n| n.strip_prefix(&self.prefix)).and_then(|n| n.strip_suffix(&self.suffix)) else {
+                continue;
+            };
+            // Only names the pattern would produce, so `1` and `0001` are not both frame 1.
+            match digits.parse::<u32>() {
+                Ok(frame) if digits.bytes().all(|b| b.is_ascii_digit()) && self.path(frame).file_name() == Some(&name) => frames.push(frame),
+                _ => {}
+            }
+        }
+        frames.sort_unstable();
+        Ok(frames)
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = format!("{}{}{}", self.prefix, "#".repeat(self.width), self.suffix);
+        write!(f, "{}", self.dir.join(name).display())
+    }
+}
+
+/// Sorted frame numbers covered by `ranges`, without repeats.
+pub fn expand(ranges: &[RangeInclusive<u32>]) -> Vec<u32> {
+    let mut frames: Vec<u32> = ranges.iter().flat_map(Clone::clone).collect();
+    frames.sort_unstable();
+    frames.dedup();
+    frames
+}
+
+/// What happened to one frame.
+#[derive(Debug)]
+pub enum Outcome {
+    Processed,
+    /// The output is newer than the input, from an earlier run with the same options.
+    Skipped,
+    /// The input frame does not exist.
+    Missing,
+    Failed(ImageError),
+}
+
+#[derive(Debug)]
+pub struct Summary {
+    pub input: Pattern,
+    pub output: Pattern,
+    pub processed: usize,
+    pub skipped: usize,
+    pub missing: Vec<u32>,
+    pub failed: Vec<(u32, ImageError)>,
+    pub elapsed: Duration,
+}
+
+impl Summary {
+    pub fn is_success(&self) -> bool {
+        self.missing.is_empty() && self.failed.is_empty()
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} -> {}: {} processed, {} skipped, {} missing, {} failed in {:.1}s",
+            self.input,
+            self.output,
+            self.processed,
+            self.skipped,
+            self.missing.len(),
+            self.failed.len(),
+            self.elapsed.as_secs_f64(),
+        )
+    }
+}
+
+/// Runs `process` on every frame in parallel, on the current rayon pool. Frames whose output
+/// is up to date are skipped unless `overwrite` is set or `options` differ from the ones
+/// recorded in the output's [manifest](Pattern::manifest) by the last run.
+pub fn run<F>(input: &Pattern, output: &Pattern, frames: &[u32], overwrite: bool, options: &str, process: F) -> Summary
+where
+    F: Fn(&Path, &Path) -> Result<(), ImageError> + Sync,
+{
+    let start = Instant::now();
+    let manifest = output.manifest();
+    let same_options = std::fs::read_to_string(&manifest).is_ok_and(|recorded| recorded == options);
+    if !same_options {
+        // Until every frame is redone, the outputs are a mix of old and new options.
+        let _ = std::fs::remove_file(&manifest);
+    }
+    let skip = !overwrite && same_options;
+    let outcomes: Vec<(u32, Outcome)> = frames
+        .par_iter()
+        .map(|&frame| {
+            let (src, dst) = (input.path(frame), output.path(frame));
+            let outcome = if !src.is_file() {
+                Outcome::Missing
+            } else if skip && up_to_date(&src, &dst) {
+                Outcome::Skipped
+            } else {
+                match process(&src, &dst) {
+                    Ok(()) => Outcome::Processed,
+                    Err(e) => Outcome::Failed(e),
+                }
+            };
+            (frame, outcome)
+        })
+        .collect();
+
+    let mut summary = Summary {
+        input: input.clone(),
+        output: output.clone(),
+        processed: 0,
+        skipped: 0,
+        missing: Vec::new(),
+        failed: Vec::new(),
+        elapsed: Duration::ZERO,
+    };
+    for (frame, outcome) in outcomes {
+        match outcome {
+            Outcome::Processed => summary.processed += 1,
+            Outcome::Skipped => summary.skipped += 1,
+            Outcome::Missing => summary.missing.push(frame),
+            Outcome::Failed(e) => summary.failed.push((frame, e)),
+        }
+    }
+    // A frame that failed may still hold an output written with other options. Without a
+    // manifest the next run redoes every frame; failing to write one only costs that too.
+    if summary.failed.is_empty() && !same_options {
+        let _ = std::fs::write(&manifest, options);
+    }
+    summary.elapsed = start.elapsed();
+    summary
+}
+
+/// Whether `dst` was written after `src` last changed. Outputs are renamed into place once
+/// complete, so an existing one is never partial.
+fn up_to_date(src: &Path, dst: &Path) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified());
+    matches!((modified(src), modified(dst)), (Ok(src), Ok(dst)) if dst >= src)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TempDir;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_parse() {
+        let hashes = Pattern::parse(Path::new("renders/fire_####.png")).unwrap();
+        assert_eq!(hashes.path(7), PathBuf::from("renders/fire_0007.png"));
+        assert_eq!(hashes.path(12345), PathBuf::from("renders/fire_12345.png"));
+        assert_eq!(hashes.to_string(), Path::new("renders").join("fire_####.png").display().to_string());
+        assert_eq!(hashes.manifest(), PathBuf::from("renders/.fire_####.png.unmult"));
+
+        let printf = Pattern::parse(Path::new("fire_%04d.png")).unwrap();
+        assert_eq!(printf, Pattern { dir: PathBuf::new(), ..hashes.clone() });
+        assert_eq!(Pattern::parse(Path::new("f%d.tif")).unwrap().path(42), PathBuf::from("f42.tif"));
+
+        assert_eq!(Pattern::parse(Path::new("fire.png")), None);
+        assert_eq!(Pattern::parse(Path::new("100%.png")), None);
+        assert_eq!(Pattern::parse(Path::new("f%4d.png")), None);
+        // Only the file name is a pattern.
+        assert_eq!(Pattern::parse(Path::new("take#2/fire.png")), None);
+    }
+
+    #[test]
+    fn test_expand() {
+        assert_eq!(expand(&[5..=7, 1..=2, 6..=8]), [1, 2, 5, 6, 7, 8]);
+        assert_eq!(expand(&[3..=3]), [3]);
+    }
+
+    #[test]
+    fn test_scan() {
+        let temp = TempDir::new("scan");
+        let dir = temp.path();
+        for name in ["a_0001.png", "a_0003.png", "a_10000.png", "a_2.png", "a_0004.tif", "b_0005.png", "a_00x1.png"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        let pattern = Pattern::parse(&dir.join("a_####.png")).unwrap();
+        assert_eq!(pattern.scan().unwrap(), [1, 3, 10000]);
+    }
+
+    #[test]
+    fn test_run_skips_finished_frames() {
+        let temp = TempDir::new("run");
+        let dir = temp.path();
+        for frame in [1, 2, 4] {
+            std::fs::write(dir.join(format!("in.{frame:03}.png")), b"in").unwrap();
+        }
+        let (input, output) = (Pattern::parse(&dir.join("in.###.png")).unwrap(), Pattern::parse(&dir.join("out.%02d.png")).unwrap());
+        let calls = AtomicUsize::new(0);
+        let copy = |src: &Path, dst: &Path| {
+            calls.fetch_add(1, Ordering::Relaxed);
+            if src.ends_with("in.004.png") {
+                return Err(ImageError::UnsupportedOutput(dst.to_path_buf()));
+            }
+            std::fs::copy(src, dst).unwrap();
+            Ok(())
+        };
+
+        let summary = run(&input, &output, &[1, 2, 3, 4], false, "unmult", copy);
+        assert_eq!((summary.processed, summary.skipped, &summary.missing[..]), (2, 0, &[3][..]));
+        assert_eq!(summary.failed.iter().map(|(frame, _)| *frame).collect::<Vec<_>>(), [4]);
+        assert!(!summary.is_success());
+        assert!(dir.join("out.01.png").is_file() && dir.join("out.02.png").is_file());
+        // A failed frame may hold an output from other options, so the run records none.
+        assert!(!output.manifest().exists());
+
+        let summary = run(&input, &output, &[1, 2], false, "unmult", copy);
+        assert_eq!((summary.processed, summary.skipped), (2, 0));
+        assert_eq!(std::fs::read_to_string(output.manifest()).unwrap(), "unmult");
+
+        let summary = run(&input, &output, &[1, 2], false, "unmult", copy);
+        assert_eq!((summary.processed, summary.skipped), (0, 2));
+        assert!(summary.is_success());
+        assert_eq!(calls.load(Ordering::Relaxed), 5);
+
+        // Other options redo every frame.
+        let summary = run(&input, &output, &[1, 2], false, "unscreen", copy);
+        assert_eq!((summary.processed, summary.skipped), (2, 0));
+        assert_eq!(std::fs::read_to_string(output.manifest()).unwrap(), "unscreen");
+
+        let summary = run(&input, &output, &[1, 2], true, "unscreen", copy);
+        assert_eq!((summary.processed, summary.skipped), (2, 0));
+        assert!(summary.to_string().contains("2 processed, 0 skipped, 0 missing, 0 failed"));
+    }
+}
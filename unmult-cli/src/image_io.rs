@@ -0,0 +1,266 @@
+//! Loading, processing and saving image files at their own depth.
+
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use exr::compression::Compression;
+use image::{ColorType, DynamicImage, ImageBuffer, ImageFormat, Rgba};
+use unmult_core::buffer::{convert_buffer, BufferError, ChannelOrder, ImageView, ImageViewMut};
+use unmult_core::parallel::TileRenderer;
+use unmult_core::rgba_to_yuv::PixelCompute;
+use unmult_core::settings::{Quantize, Settings};
+
+use crate::args::OutputDepth;
+use crate::exr_io::{self, ExrSource};
+
+/// Packed RGBA channels.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pixels {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    F32(Vec<f32>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Pixels,
+    /// The file an EXR input came from, written back around the pixels when saving to EXR.
+    pub exr: Option<ExrSource>,
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    Read { path: PathBuf, source: Box<dyn Error + Send + Sync> },
+    Write { path: PathBuf, source: Box<dyn Error + Send + Sync> },
+    /// An EXR without R, G and B channels in any layer.
+    NoRgb(PathBuf),
+    /// The output extension is not PNG, TIFF or EXR.
+    UnsupportedOutput(PathBuf),
+    Buffer(BufferError),
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::Read { path, source } => write!(f, "cannot read {}: {source}", path.display()),
+            ImageError::Write { path, source } => write!(f, "cannot write {}: {source}", path.display()),
+            ImageError::NoRgb(path) => write!(f, "cannot read {}: no layer has R, G and B channels", path.display()),
+            ImageError::UnsupportedOutput(path) => write!(f, "cannot write {}: output must be .png, .tif, .tiff or .exr", path.display()),
+            ImageError::Buffer(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for ImageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ImageError::Read { source, .. } | ImageError::Write { source, .. } => Some(source.as_ref()),
+            ImageError::NoRgb(_) | ImageError::UnsupportedOutput(_) => None,
+            ImageError::Buffer(e) => Some(e),
+        }
+    }
+}
+
+impl From<BufferError> for ImageError {
+    fn from(e: BufferError) -> Self {
+        ImageError::Buffer(e)
+    }
+}
+
+/// Reads PNG, TIFF, JPEG or EXR. 16-bit and float files keep their depth; everything else is
+/// 8-bit. Files without alpha get an opaque one.
+pub fn load(path: &Path) -> Result<Image, ImageError> {
+    let read_error = |source| ImageError::Read { path: path.to_path_buf(), source };
+    if ImageFormat::from_path(path).ok() == Some(ImageFormat::OpenExr) {
+        return exr_io::read(path).map_err(|e| read_error(e.into()))?.ok_or_else(|| ImageError::NoRgb(path.to_path_buf()));
+    }
+    let img = image::open(path).map_err(|e| read_error(e.into()))?;
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let pixels = match img.color() {
+        ColorType::Rgb32F | ColorType::Rgba32F => Pixels::F32(img.into_rgba32f().into_raw()),
+        ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16 => Pixels::U16(img.into_rgba16().into_raw()),
+        _ => Pixels::U8(img.into_rgba8().into_raw()),
+    };
+    Ok(Image { width, height, pixels, exr: None })
+}
+
+/// Writes PNG, TIFF or EXR, by extension, at `depth` or the image's own. PNG has no float
+/// channels, so float images are written to it as 16-bit; EXR is written as described by
+/// [`exr_io::write`], and `compression` applies only to it. An existing file is replaced
+/// only once the new one is complete.
+pub fn save(image: Image, path: &Path, depth: Option<OutputDepth>, compression: Option<Compression>) -> Result<(), ImageError> {
+    let format = output_format(path)?;
+    // Written beside the target and renamed, so a file at `path` is always complete.
+    let partial = path.with_file_name(format!(".{}.partial", path.file_name().unwrap_or_default().to_string_lossy()));
+    let written = match format {
+        ImageFormat::OpenExr => exr_io::write(image, &partial, depth, compression),
+        _ => write(&image, &partial, format, depth),
+    };
+    let result = written.and_then(|()| Ok(std::fs::rename(&partial, path)?));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&partial);
+    }
+    result.map_err(|source| ImageError::Write { path: path.to_path_buf(), source })
+}
+
+fn write(image: &Image, path: &Path, format: ImageFormat, depth: Option<OutputDepth>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut depth = depth.unwrap_or(match image.pixels {
+        Pixels::U8(_) => OutputDepth::U8,
+        Pixels::U16(_) => OutputDepth::U16,
+        Pixels::F32(_) => OutputDepth::F32,
+    });
+    if format == ImageFormat::Png && depth == OutputDepth::F32 {
+        depth = OutputDepth::U16;
+    }
+    let (width, height) = (image.width as u32, image.height as u32);
+    let img = match image.convert(depth)? {
+        Pixels::U8(data) => DynamicImage::ImageRgba8(ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, data).expect("image matches its size")),
+        Pixels::U16(data) => DynamicImage::ImageRgba16(ImageBuffer::<Rgba<u16>, _>::from_raw(width, height, data).expect("image matches its size")),
+        Pixels::F32(data) => DynamicImage::ImageRgba32F(ImageBuffer::<Rgba<f32>, _>::from_raw(width, height, data).expect("image matches its size")),
+    };
+    Ok(img.save_with_format(path, format)?)
+}
+
+/// Format written for `path`'s extension.
+pub fn output_format(path: &Path) -> Result<ImageFormat, ImageError> {
+    match ImageFormat::from_path(path) {
+        Ok(format @ (ImageFormat::Png | ImageFormat::Tiff | ImageFormat::OpenExr)) => Ok(format),
+        _ => Err(ImageError::UnsupportedOutput(path.to_path_buf())),
+    }
+}
+
+impl Image {
+    /// Applies `settings` to every pixel.
+    pub fn process(self, settings: &Settings, renderer: &TileRenderer) -> Result<Image, ImageError> {
+        fn run<T: PixelCompute + Send + Sync>(src: &[T], image: &Image, settings: &Settings, renderer: &TileRenderer) -> Result<Vec<T>, BufferError> {
+            let mut dst = vec![T::ZERO; src.len()];
+            let src = ImageView::packed(src, image.width, image.height, ChannelOrder::Rgba)?;
+            renderer.process(&src, &mut ImageViewMut::packed(&mut dst, image.width, image.height, ChannelOrder::Rgba)?, settings)?;
+            Ok(dst)
+        }
+        let pixels = match &self.pixels {
+            Pixels::U8(p) => Pixels::U8(run(p, &self, settings, renderer)?),
+            Pixels::U16(p) => Pixels::U16(run(p, &self, settings, renderer)?),
+            Pixels::F32(p) => Pixels::F32(run(p, &self, settings, renderer)?),
+        };
+        Ok(Image { pixels, ..self })
+    }
+
+    /// The pixels at `depth`, rounding to integer depths.
+    pub fn convert(&self, depth: OutputDepth) -> Result<Pixels, BufferError> {
+        fn to<S: PixelCompute, D: PixelCompute>(src: &[S], image: &Image) -> Result<Vec<D>, BufferError> {
+            let mut dst = vec![D::ZERO; src.len()];
+            let src = ImageView::packed(src, image.width, image.height, ChannelOrder::Rgba)?;
+            convert_buffer(&src, &mut ImageViewMut::packed(&mut dst, image.width, image.height, ChannelOrder::Rgba)?, Quantize::Round)?;
+            Ok(dst)
+        }
+        fn from<S: PixelCompute>(src: &[S], image: &Image, depth: OutputDepth) -> Result<Pixels, BufferError> {
+            Ok(match depth {
+                OutputDepth::U8 => Pixels::U8(to(src, image)?),
+                OutputDepth::U16 => Pixels::U16(to(src, image)?),
+                OutputDepth::F32 => Pixels::F32(to(src, image)?),
+            })
+        }
+        match (&self.pixels, depth) {
+            (Pixels::U8(_), OutputDepth::U8) | (Pixels::U16(_), OutputDepth::U16) | (Pixels::F32(_), OutputDepth::F32) => Ok(self.pixels.clone()),
+            (Pixels::U8(p), _) => from(p, self, depth),
+            (Pixels::U16(p), _) => from(p, self, depth),
+            (Pixels::F32(p), _) => from(p, self, depth),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TempDir;
+    use unmult_core::settings::Mode;
+
+    fn gradient() -> Image {
+        let (width, height) = (16, 8);
+        let pixels = (0..width * height).flat_map(|i| [(i * 2) as u8, (i * 3) as u8, 255 - i as u8, 255]).collect();
+        Image { width, height, pixels: Pixels::U8(pixels), exr: None }
+    }
+
+    fn at(image: &Image, depth: OutputDepth) -> Image {
+        Image { pixels: image.convert(depth).unwrap(), ..image.clone() }
+    }
+
+    #[test]
+    fn test_png_round_trip() {
+        let dir = TempDir::new("png_round_trip");
+        let path = dir.join("round_trip.png");
+        let image = gradient();
+        save(image.clone(), &path, None, None).unwrap();
+        assert_eq!(load(&path).unwrap(), image);
+
+        save(image.clone(), &path, Some(OutputDepth::U16), None).unwrap();
+        let wide = load(&path).unwrap();
+        assert!(matches!(wide.pixels, Pixels::U16(_)));
+        assert_eq!(at(&wide, OutputDepth::U8), image);
+
+        // PNG cannot hold floats.
+        save(image, &path, Some(OutputDepth::F32), None).unwrap();
+        assert!(matches!(load(&path).unwrap().pixels, Pixels::U16(_)));
+    }
+
+    #[test]
+    fn test_tiff_float_round_trip() {
+        let dir = TempDir::new("tiff_float_round_trip");
+        let path = dir.join("round_trip.tif");
+        let image = at(&gradient(), OutputDepth::F32);
+        save(image.clone(), &path, None, None).unwrap();
+        assert_eq!(load(&path).unwrap(), image);
+    }
+
+    #[test]
+    fn test_exr_round_trip() {
+        let dir = TempDir::new("exr_round_trip");
+        let path = dir.join("round_trip.exr");
+        let image = at(&gradient(), OutputDepth::F32);
+        save(image.clone(), &path, None, None).unwrap();
+        let loaded = load(&path).unwrap();
+        assert_eq!(loaded.pixels, image.pixels);
+        assert!(loaded.exr.is_some());
+
+        // Superwhites are not clamped on the float path.
+        let hdr = Image { width: 1, height: 1, pixels: Pixels::F32(vec![4.0, 2.0, 1.0, 1.0]), exr: None };
+        let Pixels::F32(out) = hdr.process(&Settings::default(), &TileRenderer::new()).unwrap().pixels else { unreachable!() };
+        assert_eq!(out, [1.0, 0.5, 0.25, 4.0]);
+    }
+
+    #[test]
+    fn test_jpeg_input_is_opaque() {
+        let dir = TempDir::new("jpeg_input_is_opaque");
+        let path = dir.join("opaque.jpg");
+        let rgb = image::RgbImage::from_fn(8, 8, |x, y| image::Rgb([x as u8 * 30, y as u8 * 30, 90]));
+        rgb.save(&path).unwrap();
+        let Pixels::U8(pixels) = load(&path).unwrap().pixels else { panic!("expected 8-bit") };
+        assert!(pixels.chunks_exact(4).all(|p| p[3] == 255));
+    }
+
+    #[test]
+    fn test_process_matches_core() {
+        let image = gradient();
+        let out = image.clone().process(&Settings::default(), &TileRenderer::new()).unwrap();
+        let (Pixels::U8(src), Pixels::U8(dst)) = (&image.pixels, &out.pixels) else { unreachable!() };
+        for (s, d) in src.chunks_exact(4).zip(dst.chunks_exact(4)) {
+            let mut expected = unmult_core::Pixel8::default();
+            unmult_core::inner_render(&unmult_core::Pixel8 { red: s[0], green: s[1], blue: s[2], alpha: s[3] }, &mut expected);
+            assert_eq!(d, [expected.red, expected.green, expected.blue, expected.alpha]);
+        }
+
+        let wide = at(&image, OutputDepth::U16).process(&Settings::new(Mode::Remult), &TileRenderer::new()).unwrap();
+        assert!(matches!(wide.pixels, Pixels::U16(_)));
+    }
+
+    #[test]
+    fn test_unsupported_output() {
+        assert!(matches!(save(gradient(), Path::new("out.jpg"), None, None), Err(ImageError::UnsupportedOutput(_))));
+        assert!(matches!(load(Path::new("does/not/exist.png")), Err(ImageError::Read { .. })));
+        assert!(matches!(load(Path::new("does/not/exist.exr")), Err(ImageError::Read { .. })));
+    }
+}
@@ -0,0 +1,112 @@
+//! `unmult`: the effect for image files, without After Effects.
+
+mod args;
+mod exr_io;
+mod frames;
+mod image_io;
+
+use std::error::Error;
+use std::path::Path;
+use std::process::ExitCode;
+
+use unmult_core::parallel::TileRenderer;
+
+use args::{Command, Options};
+use frames::Pattern;
+use image_io::ImageError;
+
+fn main() -> ExitCode {
+    match args::parse(std::env::args().skip(1)) {
+        Ok(Command::Run(options)) => match run(&options) {
+            Ok(true) => ExitCode::SUCCESS,
+            Ok(false) => ExitCode::FAILURE,
+            Err(e) => {
+                eprintln!("unmult: {e}");
+                ExitCode::FAILURE
+            }
+        },
+        Ok(Command::Help) => {
+            print!("{}", args::usage());
+            ExitCode::SUCCESS
+        }
+        Ok(Command::Version) => {
+            println!("unmult {}", env!("CARGO_PKG_VERSION"));
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("unmult: {e}\n\n{}", args::usage());
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Processes a file or a sequence. `Ok(false)` if some frames of a sequence failed.
+fn run(options: &Options) -> Result<bool, Box<dyn Error>> {
+    // Fail on a bad output path before doing any work.
+    image_io::output_format(&options.output)?;
+    let renderer = match options.threads {
+        Some(threads) => TileRenderer::new().with_threads(threads)?,
+        None => TileRenderer::new(),
+    };
+    let process = |input: &Path, output: &Path| -> Result<(), ImageError> {
+        let image = image_io::load(input)?.process(&options.settings, &renderer)?;
+        image_io::save(image, output, options.depth, options.compression)
+    };
+    let (Some(input), Some(output)) = (Pattern::parse(&options.input), Pattern::parse(&options.output)) else {
+        process(&options.input, &options.output)?;
+        return Ok(true);
+    };
+
+    let frames = match &options.frames {
+        Some(ranges) => frames::expand(ranges),
+        None => input.scan().map_err(|e| format!("cannot list frames of {input}: {e}"))?,
+    };
+    if frames.is_empty() {
+        return Err(format!("no frames match {input}").into());
+    }
+    if let Some(dir) = options.output.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir).map_err(|e| format!("cannot create {}: {e}", dir.display()))?;
+    }
+    // Frames and their tiles share the renderer's pool.
+    let summary = renderer.install(|| frames::run(&input, &output, &frames, options.overwrite, &options.fingerprint(), process));
+    for frame in &summary.missing {
+        eprintln!("unmult: missing {}", input.path(*frame).display());
+    }
+    for (_, e) in &summary.failed {
+        eprintln!("unmult: {e}");
+    }
+    println!("{summary}");
+    Ok(summary.is_success())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    /// Directory for one test's files, removed with them when dropped.
+    pub struct TempDir(PathBuf);
+
+    impl TempDir {
+        /// Empty directory named after the process and `test`, so tests don't share files.
+        pub fn new(test: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("unmult-cli-{}-{test}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        pub fn path(&self) -> &Path {
+            &self.0
+        }
+
+        pub fn join(&self, name: &str) -> PathBuf {
+            self.0.join(name)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+}
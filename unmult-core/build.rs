@@ -0,0 +1,65 @@
+use std::path::Path;
+use std::{fs::File, io::BufWriter};
+use std::io::Write;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // Generate the LUTs
+    let out_path = Path::new("src/generated_lut.rs");
+    let mut file = BufWriter::new(File::create(out_path).unwrap());
+
+    writeln!(file, "pub static LUT: [u8; 65536] = [").unwrap();
+
+    for i in 0..=0xFFFF {
+        let alpha = (i >> 8) as u8;
+        let value = (i & 0xFF) as u8;
+        let result = if alpha == 0 {
+            0
+        } else {
+            let temp = ((value as u32) << 8) / (alpha as u32);
+            if temp > 0xFF {
+                0xFF
+            } else {
+                temp as u8
+            }
+        };
+        writeln!(file, "    {},", result).unwrap();
+    }
+
+    writeln!(file, "];").unwrap();
+
+    // Round-to-nearest variant of LUT: round(value * 255 / alpha), saturating.
+    writeln!(file, "pub static LUT_ROUND: [u8; 65536] = [").unwrap();
+
+    for i in 0..=0xFFFF {
+        let alpha = (i >> 8) as u32;
+        let value = (i & 0xFF) as u32;
+        let result = if alpha == 0 {
+            0
+        } else {
+            ((value * 0xFF * 2 + alpha) / (alpha * 2)).min(0xFF)
+        };
+        writeln!(file, "    {},", result).unwrap();
+    }
+
+    writeln!(file, "];").unwrap();
+
+    // Unscreen mirrors the unmult LUT around white: indexed by the darkest channel's
+    // distance below white, it maps a value to `255 - LUT[(dark << 8) + (255 - value)]`.
+    writeln!(file, "pub static UNSCREEN_LUT: [u8; 65536] = [").unwrap();
+
+    for i in 0..=0xFFFF {
+        let dark = (i >> 8) as u8;
+        let value = (i & 0xFF) as u8;
+        let result = if dark == 0 {
+            0
+        } else {
+            let temp = (((0xFF - value) as u32) << 8) / (dark as u32);
+            0xFF - temp.min(0xFF) as u8
+        };
+        writeln!(file, "    {},", result).unwrap();
+    }
+
+    writeln!(file, "];").unwrap();
+}
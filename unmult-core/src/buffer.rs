@@ -0,0 +1,538 @@
+use std::fmt;
+use std::mem::size_of;
+
+use crate::rgba_to_yuv::{PixelCompute, RgbaPixel, YuvFormat, YuvaPixel};
+use crate::settings::{Mode, Quantize, Settings};
+
+/// Order of the four channels inside a pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChannelOrder {
+    Rgba,
+    /// Premiere's `Bgra4444_*` formats.
+    Bgra,
+    /// After Effects' `PF_Pixel*` layout.
+    Argb,
+}
+
+impl ChannelOrder {
+    /// Premiere's `VUYA_4444_*` layout. YUV buffers keep Y, U and V in the red, green and
+    /// blue slots of their order.
+    pub const VUYA: ChannelOrder = ChannelOrder::Bgra;
+
+    /// Offsets of red, green, blue and alpha inside a pixel.
+    #[inline]
+    pub const fn offsets(self) -> [usize; 4] {
+        match self {
+            ChannelOrder::Rgba => [0, 1, 2, 3],
+            ChannelOrder::Bgra => [2, 1, 0, 3],
+            ChannelOrder::Argb => [1, 2, 3, 0],
+        }
+    }
+
+    #[inline]
+    pub(crate) fn read<T: PixelCompute>(self, px: &[T]) -> RgbaPixel<T> {
+        let [r, g, b, a] = self.offsets();
+        RgbaPixel::new(px[r], px[g], px[b], px[a])
+    }
+
+    #[inline]
+    pub(crate) fn write<T: PixelCompute>(self, px: &mut [T], pixel: &RgbaPixel<T>) {
+        let [r, g, b, a] = self.offsets();
+        px[r] = pixel.get_red();
+        px[g] = pixel.get_green();
+        px[b] = pixel.get_blue();
+        px[a] = pixel.get_alpha();
+    }
+
+    #[inline]
+    pub(crate) fn read_yuva<T: PixelCompute>(self, px: &[T]) -> YuvaPixel<T> {
+        let [y, u, v, a] = self.offsets();
+        YuvaPixel::new(px[y], px[u], px[v], px[a])
+    }
+
+    #[inline]
+    pub(crate) fn write_yuva<T: PixelCompute>(self, px: &mut [T], pixel: &YuvaPixel<T>) {
+        let [y, u, v, a] = self.offsets();
+        px[y] = pixel.get_y();
+        px[u] = pixel.get_u();
+        px[v] = pixel.get_v();
+        px[a] = pixel.get_alpha();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferError {
+    /// The row stride is not a multiple of the channel size or is shorter than a row.
+    InvalidStride { stride: usize, min: usize },
+    /// The slice is too short for the given dimensions and stride.
+    TooSmall { len: usize, required: usize },
+    /// Source and destination dimensions differ.
+    SizeMismatch { src: (usize, usize), dst: (usize, usize) },
+}
+
+impl fmt::Display for BufferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BufferError::InvalidStride { stride, min } => write!(f, "invalid row stride {stride} bytes (need a multiple of the channel size, at least {min})"),
+            BufferError::TooSmall { len, required } => write!(f, "buffer holds {len} channels but {required} are required"),
+            BufferError::SizeMismatch { src, dst } => write!(f, "source is {}x{} but destination is {}x{}", src.0, src.1, dst.0, dst.1),
+        }
+    }
+}
+
+impl std::error::Error for BufferError {}
+
+/// Checks the layout and returns the stride in channels.
+fn validate<T>(len: usize, width: usize, height: usize, stride: usize) -> Result<usize, BufferError> {
+    let row_len = width * 4;
+    let min = row_len * size_of::<T>();
+    if !stride.is_multiple_of(size_of::<T>()) || stride < min {
+        return Err(BufferError::InvalidStride { stride, min });
+    }
+    let stride = stride / size_of::<T>();
+    let required = if height == 0 { 0 } else { (height - 1) * stride + row_len };
+    if len < required {
+        return Err(BufferError::TooSmall { len, required });
+    }
+    Ok(stride)
+}
+
+/// Read-only view of a four-channel image.
+///
+/// `stride` is the distance between the starts of two rows in bytes, like AE's `rowbytes`.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageView<'a, T: PixelCompute> {
+    data: &'a [T],
+    width: usize,
+    height: usize,
+    stride: usize,
+    order: ChannelOrder,
+}
+
+impl<'a, T> ImageView<'a, T> where T: PixelCompute {
+    pub fn new(data: &'a [T], width: usize, height: usize, stride: usize, order: ChannelOrder) -> Result<Self, BufferError> {
+        let stride = validate::<T>(data.len(), width, height, stride)?;
+        Ok(Self { data, width, height, stride, order })
+    }
+
+    /// View of tightly packed rows.
+    pub fn packed(data: &'a [T], width: usize, height: usize, order: ChannelOrder) -> Result<Self, BufferError> {
+        Self::new(data, width, height, width * 4 * size_of::<T>(), order)
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize { self.width }
+    #[inline]
+    pub fn height(&self) -> usize { self.height }
+    #[inline]
+    pub fn order(&self) -> ChannelOrder { self.order }
+    /// Row stride in bytes.
+    #[inline]
+    pub fn stride(&self) -> usize { self.stride * size_of::<T>() }
+
+    /// Channels of row `y`, without padding.
+    #[inline]
+    pub fn row(&self, y: usize) -> &'a [T] {
+        let start = y * self.stride;
+        &self.data[start..start + self.width * 4]
+    }
+
+    /// The `width` x `height` region at `x`, `y`, or `None` if it does not fit.
+    pub fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> Option<ImageView<'a, T>> {
+        let range = crop_range(self.width, self.height, self.stride, x, y, width, height)?;
+        Some(ImageView { data: &self.data[range], width, height, stride: self.stride, order: self.order })
+    }
+}
+
+/// Mutable view of a four-channel image. See [`ImageView`].
+#[derive(Debug)]
+pub struct ImageViewMut<'a, T: PixelCompute> {
+    data: &'a mut [T],
+    width: usize,
+    height: usize,
+    stride: usize,
+    order: ChannelOrder,
+}
+
+impl<'a, T> ImageViewMut<'a, T> where T: PixelCompute {
+    pub fn new(data: &'a mut [T], width: usize, height: usize, stride: usize, order: ChannelOrder) -> Result<Self, BufferError> {
+        let stride = validate::<T>(data.len(), width, height, stride)?;
+        Ok(Self { data, width, height, stride, order })
+    }
+
+    /// View of tightly packed rows.
+    pub fn packed(data: &'a mut [T], width: usize, height: usize, order: ChannelOrder) -> Result<Self, BufferError> {
+        Self::new(data, width, height, width * 4 * size_of::<T>(), order)
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize { self.width }
+    #[inline]
+    pub fn height(&self) -> usize { self.height }
+    #[inline]
+    pub fn order(&self) -> ChannelOrder { self.order }
+    /// Row stride in bytes.
+    #[inline]
+    pub fn stride(&self) -> usize { self.stride * size_of::<T>() }
+
+    /// Channels of row `y`, without padding.
+    #[inline]
+    pub fn row_mut(&mut self, y: usize) -> &mut [T] {
+        let start = y * self.stride;
+        &mut self.data[start..start + self.width * 4]
+    }
+
+    /// Channels of every row, without padding.
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [T]> {
+        let row_len = self.width * 4;
+        self.data.chunks_mut(self.stride.max(1)).take(self.height).map(move |row| &mut row[..row_len])
+    }
+
+    /// Mutable [`ImageView::crop`].
+    pub fn crop_mut(&mut self, x: usize, y: usize, width: usize, height: usize) -> Option<ImageViewMut<'_, T>> {
+        let range = crop_range(self.width, self.height, self.stride, x, y, width, height)?;
+        Some(ImageViewMut { data: &mut self.data[range], width, height, stride: self.stride, order: self.order })
+    }
+
+    /// Sets every channel to `value`, leaving row padding untouched.
+    pub fn fill(&mut self, value: T) {
+        self.rows_mut().for_each(|row| row.fill(value));
+    }
+}
+
+/// Channel range of a crop, with `stride` in channels.
+#[allow(clippy::too_many_arguments)]
+fn crop_range(full_width: usize, full_height: usize, stride: usize, x: usize, y: usize, width: usize, height: usize) -> Option<std::ops::Range<usize>> {
+    if x.checked_add(width)? > full_width || y.checked_add(height)? > full_height {
+        return None;
+    }
+    if width == 0 || height == 0 {
+        return Some(0..0);
+    }
+    let start = y * stride + x * 4;
+    Some(start..start + (height - 1) * stride + width * 4)
+}
+
+pub(crate) fn check_size<S: PixelCompute, D: PixelCompute>(src: &ImageView<S>, dst: &ImageViewMut<D>) -> Result<(), BufferError> {
+    if src.width != dst.width || src.height != dst.height {
+        return Err(BufferError::SizeMismatch { src: (src.width, src.height), dst: (dst.width, dst.height) });
+    }
+    Ok(())
+}
+
+/// Applies `settings` to every pixel of `src`, writing into `dst`.
+///
+/// The views may use different strides and channel orders but must have the same size.
+/// Row padding in `dst` is left untouched. Plain unmult rows with matching channel orders
+/// go through the SIMD kernels.
+pub fn process_buffer<T: PixelCompute>(src: &ImageView<T>, dst: &mut ImageViewMut<T>, settings: &Settings) -> Result<(), BufferError> {
+    check_size(src, dst)?;
+    let (src_order, dst_order) = (src.order, dst.order);
+    for y in 0..src.height {
+        process_span(src.row(y), src_order, dst.row_mut(y), dst_order, settings, (0, y));
+    }
+    Ok(())
+}
+
+/// [`process_buffer`] for YUV buffers in `format`, such as Premiere's VUYA worlds.
+///
+/// Each pixel is converted to RGB, processed and converted back.
+pub fn process_yuv_buffer<T: PixelCompute>(src: &ImageView<T>, dst: &mut ImageViewMut<T>, settings: &Settings, format: YuvFormat) -> Result<(), BufferError> {
+    check_size(src, dst)?;
+    let (src_order, dst_order) = (src.order, dst.order);
+    for y in 0..src.height {
+        process_yuv_span(src.row(y), src_order, dst.row_mut(y), dst_order, settings, format, (0, y));
+    }
+    Ok(())
+}
+
+/// Unmultiplies every pixel of `src` into `dst`. See [`process_buffer`].
+pub fn unmult_buffer<T: PixelCompute>(src: &ImageView<T>, dst: &mut ImageViewMut<T>) -> Result<(), BufferError> {
+    process_buffer(src, dst, &Settings::new(Mode::Unmult))
+}
+
+/// Composites every straight pixel of `src` over black into `dst`. See [`process_buffer`].
+pub fn remult_buffer<T: PixelCompute>(src: &ImageView<T>, dst: &mut ImageViewMut<T>) -> Result<(), BufferError> {
+    process_buffer(src, dst, &Settings::new(Mode::Remult))
+}
+
+/// Unscreens every pixel of `src` into `dst`. See [`process_buffer`].
+pub fn unscreen_buffer<T: PixelCompute>(src: &ImageView<T>, dst: &mut ImageViewMut<T>) -> Result<(), BufferError> {
+    process_buffer(src, dst, &Settings::new(Mode::Unscreen))
+}
+
+/// Converts every pixel of `src` to the channel type and order of `dst`.
+///
+/// Values are rescaled between the depths' white levels; integer destinations are
+/// quantised with `quantize`. Floats are neither clamped nor quantised.
+pub fn convert_buffer<S: PixelCompute, D: PixelCompute>(src: &ImageView<S>, dst: &mut ImageViewMut<D>, quantize: Quantize) -> Result<(), BufferError> {
+    check_size(src, dst)?;
+    let (src_order, dst_order) = (src.order, dst.order);
+    for y in 0..src.height {
+        for (x, (s, d)) in src.row(y).chunks_exact(4).zip(dst.row_mut(y).chunks_exact_mut(4)).enumerate() {
+            let p = src_order.read(s);
+            let offset = quantize.offset(x, y);
+            let convert = |c: S| D::quantize(c.to_f32(), offset);
+            dst_order.write(d, &RgbaPixel::new(convert(p.get_red()), convert(p.get_green()), convert(p.get_blue()), convert(p.get_alpha())));
+        }
+    }
+    Ok(())
+}
+
+/// Processes a run of pixels starting at image position `origin`, converting between
+/// channel orders if needed.
+pub(crate) fn process_span<T: PixelCompute>(src: &[T], src_order: ChannelOrder, dst: &mut [T], dst_order: ChannelOrder, settings: &Settings, origin: (usize, usize)) {
+    if settings.is_plain_unmult() && src_order == dst_order {
+        T::unmult_row(src, dst, src_order);
+        return;
+    }
+    let (x, y) = origin;
+    for (i, (s, d)) in src.chunks_exact(4).zip(dst.chunks_exact_mut(4)).enumerate() {
+        dst_order.write(d, &src_order.read(s).apply_at(settings, x + i, y));
+    }
+}
+
+/// [`process_span`] for YUV pixels.
+pub(crate) fn process_yuv_span<T: PixelCompute>(
+    src: &[T],
+    src_order: ChannelOrder,
+    dst: &mut [T],
+    dst_order: ChannelOrder,
+    settings: &Settings,
+    format: YuvFormat,
+    origin: (usize, usize),
+) {
+    let (x, y) = origin;
+    for (i, (s, d)) in src.chunks_exact(4).zip(dst.chunks_exact_mut(4)).enumerate() {
+        dst_order.write_yuva(d, &src_order.read_yuva(s).apply_at(settings, format, x + i, y));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{inner_render, Pixel8};
+
+    #[test]
+    fn test_crop() {
+        let mut data: Vec<u8> = (0..4 * 4 * 3).map(|i| i as u8).collect();
+        let view = ImageView::packed(&data, 4, 3, ChannelOrder::Rgba).unwrap();
+        let crop = view.crop(1, 1, 2, 2).unwrap();
+        assert_eq!(crop.row(0), &data[20..28]);
+        assert_eq!(crop.row(1), &data[36..44]);
+        assert!(view.crop(3, 0, 2, 1).is_none());
+        assert!(view.crop(0, 3, 1, 1).is_none());
+        assert_eq!(view.crop(4, 3, 0, 0).unwrap().width(), 0);
+
+        let mut dst = ImageViewMut::packed(&mut data, 4, 3, ChannelOrder::Rgba).unwrap();
+        dst.crop_mut(2, 1, 2, 2).unwrap().fill(0);
+        let zeroed: Vec<usize> = data.iter().enumerate().filter(|(_, &v)| v == 0).map(|(i, _)| i).collect();
+        assert_eq!(zeroed, [0].into_iter().chain(24..32).chain(40..48).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_unmult_buffer_u8_matches_inner_render() {
+        let src: Vec<u8> = (0..=255u8).flat_map(|v| [v, v / 2, 255 - v, v.wrapping_mul(7)]).collect();
+        let mut dst = vec![0u8; src.len()];
+        unmult_buffer(
+            &ImageView::packed(&src, 16, 16, ChannelOrder::Rgba).unwrap(),
+            &mut ImageViewMut::packed(&mut dst, 16, 16, ChannelOrder::Rgba).unwrap(),
+        ).unwrap();
+
+        for (s, d) in src.chunks_exact(4).zip(dst.chunks_exact(4)) {
+            let mut expected = Pixel8::default();
+            inner_render(&Pixel8 { red: s[0], green: s[1], blue: s[2], alpha: s[3] }, &mut expected);
+            assert_eq!(d, [expected.red, expected.green, expected.blue, expected.alpha]);
+        }
+    }
+
+    #[test]
+    fn test_unmult_buffer_ae16_matches_u8() {
+        use crate::rgba_to_yuv::Ae16;
+
+        // ARGB, as After Effects hands both depths over.
+        let src: Vec<u8> = (0..=255u8).flat_map(|v| [255, v, v / 2, 255 - v]).collect();
+        let src16: Vec<Ae16> = src.iter().map(|&v| Ae16(((v as u32 * 32768 + 127) / 255) as u16)).collect();
+        let mut dst = vec![0u8; src.len()];
+        let mut dst16 = vec![Ae16(0); src16.len()];
+        unmult_buffer(
+            &ImageView::packed(&src, 16, 16, ChannelOrder::Argb).unwrap(),
+            &mut ImageViewMut::packed(&mut dst, 16, 16, ChannelOrder::Argb).unwrap(),
+        ).unwrap();
+        unmult_buffer(
+            &ImageView::packed(&src16, 16, 16, ChannelOrder::Argb).unwrap(),
+            &mut ImageViewMut::packed(&mut dst16, 16, 16, ChannelOrder::Argb).unwrap(),
+        ).unwrap();
+
+        for (d, d16) in dst.iter().zip(&dst16) {
+            assert!((d.to_f32() - d16.to_f32()).abs() <= 2.0 / 255.0, "{d} vs {d16:?}");
+        }
+    }
+
+    #[test]
+    fn test_convert_buffer() {
+        use half::f16;
+
+        let src: Vec<u8> = (0..=255u8).flat_map(|v| [v, 255 - v, v / 3, 255]).collect();
+
+        // u8 -> f16 -> u8 is lossless with rounding, and reorders channels on the way.
+        let mut half = vec![f16::ZERO; src.len()];
+        convert_buffer(
+            &ImageView::packed(&src, 16, 16, ChannelOrder::Rgba).unwrap(),
+            &mut ImageViewMut::packed(&mut half, 16, 16, ChannelOrder::Bgra).unwrap(),
+            Quantize::Round,
+        ).unwrap();
+        assert_eq!(half[0..4], [f16::ZERO, f16::ONE, f16::ZERO, f16::ONE]);
+        let mut back = vec![0u8; src.len()];
+        convert_buffer(
+            &ImageView::packed(&half, 16, 16, ChannelOrder::Bgra).unwrap(),
+            &mut ImageViewMut::packed(&mut back, 16, 16, ChannelOrder::Rgba).unwrap(),
+            Quantize::Round,
+        ).unwrap();
+        assert_eq!(back, src);
+
+        // f64 -> u8 truncation matches `from_f32`; floats beyond white saturate.
+        let src = [0.5f64, 0.999, 2.0, -1.0];
+        let mut dst = [0u8; 4];
+        convert_buffer(
+            &ImageView::packed(&src, 1, 1, ChannelOrder::Rgba).unwrap(),
+            &mut ImageViewMut::packed(&mut dst, 1, 1, ChannelOrder::Rgba).unwrap(),
+            Quantize::Truncate,
+        ).unwrap();
+        assert_eq!(dst, [127, 254, 255, 0]);
+    }
+
+    #[test]
+    fn test_unmult_buffer_half() {
+        use half::f16;
+
+        let values = [0.5f32, 0.25, 0.0, 1.0, 0.1, 0.2, 0.4, 0.5];
+        let src: Vec<f16> = values.iter().map(|&v| f16::from_f32(v)).collect();
+        let mut dst = vec![f16::ZERO; src.len()];
+        unmult_buffer(
+            &ImageView::packed(&src, 2, 1, ChannelOrder::Rgba).unwrap(),
+            &mut ImageViewMut::packed(&mut dst, 2, 1, ChannelOrder::Rgba).unwrap(),
+        ).unwrap();
+        for (s, d) in src.chunks_exact(4).zip(dst.chunks_exact(4)) {
+            let expected = RgbaPixel::new(s[0], s[1], s[2], s[3]).unmult_rgba();
+            assert_eq!(d, [expected.get_red(), expected.get_green(), expected.get_blue(), expected.get_alpha()]);
+        }
+        assert_eq!(dst[0..4], [f16::ONE, f16::from_f32(0.5), f16::ZERO, f16::from_f32(0.5)]);
+    }
+
+    #[test]
+    fn test_process_yuv_buffer_vuya() {
+        use crate::rgba_to_yuv::{YuvMatrix, YuvRange};
+
+        let format = YuvFormat::new(YuvMatrix::Rec709, YuvRange::Full);
+        let rgb = [(0.5f32, 0.25, 0.0), (0.1, 0.2, 0.4), (0.0, 0.0, 0.0)];
+        let mut src = Vec::new();
+        for &(r, g, b) in &rgb {
+            let p = YuvaPixel::from_rgba(&RgbaPixel::new(r, g, b, 1.0), format);
+            src.extend([p.get_v(), p.get_u(), p.get_y(), p.get_alpha()]);
+        }
+        let mut dst = vec![0.0f32; src.len()];
+        process_yuv_buffer(
+            &ImageView::packed(&src, 3, 1, ChannelOrder::VUYA).unwrap(),
+            &mut ImageViewMut::packed(&mut dst, 3, 1, ChannelOrder::VUYA).unwrap(),
+            &Settings::default(),
+            format,
+        ).unwrap();
+
+        for (&(r, g, b), d) in rgb.iter().zip(dst.chunks_exact(4)) {
+            let expected = RgbaPixel::new(r, g, b, 1.0).unmult_rgba();
+            let out = YuvaPixel::new(d[2], d[1], d[0], d[3]).to_rgba(format);
+            for (x, y) in [(expected.get_red(), out.get_red()), (expected.get_green(), out.get_green()), (expected.get_blue(), out.get_blue()), (expected.get_alpha(), out.get_alpha())] {
+                assert!((x - y).abs() < 1e-5, "{expected:?} vs {out:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_premultiplied_buffer_reproduces_input() {
+        use crate::settings::Association;
+
+        let (width, height) = (32, 8);
+        let src: Vec<f32> = (0..width * height)
+            .flat_map(|i| [(i % 32) as f32 / 31.0, (i / 32) as f32 / 7.0, ((i * 13) % 17) as f32 / 16.0, 1.0])
+            .collect();
+        let mut dst = vec![0.0f32; src.len()];
+        process_buffer(
+            &ImageView::packed(&src, width, height, ChannelOrder::Rgba).unwrap(),
+            &mut ImageViewMut::packed(&mut dst, width, height, ChannelOrder::Rgba).unwrap(),
+            &Settings::default().with_association(Association::Premultiplied),
+        ).unwrap();
+
+        // Premultiplied colour added over black is the colour channels themselves.
+        for (s, d) in src.chunks_exact(4).zip(dst.chunks_exact(4)) {
+            for c in 0..3 {
+                assert!((s[c] - d[c]).abs() < 1e-6, "{s:?} -> {d:?}");
+            }
+            assert!(d[3] <= 1.0);
+        }
+    }
+
+    #[test]
+    fn test_unmult_buffer_f32_matches_unmult_rgba() {
+        let src = [0.5f32, 0.25, 0.0, 1.0, 0.1, 0.2, 0.4, 0.5];
+        let mut dst = [0.0f32; 8];
+        unmult_buffer(
+            &ImageView::packed(&src, 2, 1, ChannelOrder::Rgba).unwrap(),
+            &mut ImageViewMut::packed(&mut dst, 2, 1, ChannelOrder::Rgba).unwrap(),
+        ).unwrap();
+
+        for (s, d) in src.chunks_exact(4).zip(dst.chunks_exact(4)) {
+            let expected = RgbaPixel::new(s[0], s[1], s[2], s[3]).unmult_rgba();
+            assert_eq!(d, [expected.get_red(), expected.get_green(), expected.get_blue(), expected.get_alpha()]);
+        }
+    }
+
+    #[test]
+    fn test_unmult_buffer_stride_and_order() {
+        // 1x2 ARGB u16 with two channels of padding per row.
+        let src = [0xFFFFu16, 0x8000, 0x4000, 0, 9, 9, 0xFFFF, 0, 0, 0, 9, 9];
+        let mut dst = [7u16; 8];
+        unmult_buffer(
+            &ImageView::new(&src, 1, 2, 12, ChannelOrder::Argb).unwrap(),
+            &mut ImageViewMut::packed(&mut dst, 1, 2, ChannelOrder::Bgra).unwrap(),
+        ).unwrap();
+
+        let first = RgbaPixel::<u16>::new(0x8000, 0x4000, 0, 0xFFFF).unmult_rgba();
+        assert_eq!(dst[..4], [first.get_blue(), first.get_green(), first.get_red(), first.get_alpha()]);
+        assert_eq!(dst[4..], [0, 0, 0, 0]);
+
+        let mut padded = [7u16; 12];
+        unmult_buffer(
+            &ImageView::new(&src, 1, 2, 12, ChannelOrder::Argb).unwrap(),
+            &mut ImageViewMut::new(&mut padded, 1, 2, 12, ChannelOrder::Argb).unwrap(),
+        ).unwrap();
+        assert_eq!(padded[4..6], [7, 7]);
+        assert_eq!(padded[10..], [7, 7]);
+    }
+
+    #[test]
+    fn test_remult_buffer() {
+        let src = [255u8, 128, 0, 128, 10, 20, 30, 0];
+        let mut dst = [0u8; 8];
+        remult_buffer(
+            &ImageView::packed(&src, 2, 1, ChannelOrder::Rgba).unwrap(),
+            &mut ImageViewMut::packed(&mut dst, 2, 1, ChannelOrder::Bgra).unwrap(),
+        ).unwrap();
+        assert_eq!(dst, [0, 64, 128, 255, 0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_buffer_errors() {
+        let data = [0u8; 16];
+        assert_eq!(ImageView::new(&data, 2, 2, 7, ChannelOrder::Rgba).unwrap_err(), BufferError::InvalidStride { stride: 7, min: 8 });
+        assert_eq!(ImageView::new(&data, 2, 2, 12, ChannelOrder::Rgba).unwrap_err(), BufferError::TooSmall { len: 16, required: 20 });
+        assert_eq!(ImageView::new(&[0.0f32; 8], 2, 1, 6, ChannelOrder::Rgba).unwrap_err(), BufferError::InvalidStride { stride: 6, min: 32 });
+
+        let mut out = [0u8; 16];
+        let err = unmult_buffer(
+            &ImageView::packed(&data, 2, 2, ChannelOrder::Rgba).unwrap(),
+            &mut ImageViewMut::packed(&mut out, 4, 1, ChannelOrder::Rgba).unwrap(),
+        ).unwrap_err();
+        assert_eq!(err, BufferError::SizeMismatch { src: (2, 2), dst: (4, 1) });
+    }
+}
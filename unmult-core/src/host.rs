@@ -0,0 +1,501 @@
+//! The effect's command flow, written against a [`Host`] rather than the After Effects SDK.
+//!
+//! The plugin adapts AE and Premiere to [`Host`]; [`MockHost`](crate::mock::MockHost) runs
+//! the same flow in memory for tests.
+
+use std::fmt;
+
+use crate::buffer::{BufferError, ChannelOrder, ImageView, ImageViewMut};
+use crate::parallel::TileRenderer;
+use crate::rgba_to_yuv::{Ae16, PixelCompute, YuvFormat, YuvMatrix, YuvRange};
+use crate::roi::{self, Rect};
+use crate::settings::Settings;
+
+/// Channel type of a world.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Depth {
+    U8,
+    /// The hosts' 16bpc worlds, `0..=32768`.
+    Ae16,
+    F32,
+}
+
+impl Depth {
+    pub const fn bits(self) -> u8 {
+        match self {
+            Depth::U8 => 8,
+            Depth::Ae16 => 16,
+            Depth::F32 => 32,
+        }
+    }
+}
+
+/// Pixel format a host can hand over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Format {
+    pub depth: Depth,
+    /// Encoding of YUV worlds, `None` for RGB.
+    pub yuv: Option<YuvFormat>,
+}
+
+impl Format {
+    pub const fn rgb(depth: Depth) -> Self {
+        Self { depth, yuv: None }
+    }
+
+    pub const fn yuv(depth: Depth, matrix: YuvMatrix, range: YuvRange) -> Self {
+        Self { depth, yuv: Some(YuvFormat::new(matrix, range)) }
+    }
+}
+
+/// Formats declared to Premiere, in order of preference.
+pub const PREMIERE_FORMATS: [Format; 7] = [
+    Format::rgb(Depth::U8),
+    Format::rgb(Depth::Ae16),
+    Format::rgb(Depth::F32),
+    Format::yuv(Depth::U8, YuvMatrix::Rec601, YuvRange::Video),
+    Format::yuv(Depth::U8, YuvMatrix::Rec709, YuvRange::Video),
+    Format::yuv(Depth::F32, YuvMatrix::Rec601, YuvRange::Full),
+    Format::yuv(Depth::F32, YuvMatrix::Rec709, YuvRange::Full),
+];
+
+#[derive(Debug, Clone, Copy)]
+pub enum Pixels<'a> {
+    U8(&'a [u8]),
+    Ae16(&'a [Ae16]),
+    F32(&'a [f32]),
+}
+
+#[derive(Debug)]
+pub enum PixelsMut<'a> {
+    U8(&'a mut [u8]),
+    Ae16(&'a mut [Ae16]),
+    F32(&'a mut [f32]),
+}
+
+/// Input pixels of a render.
+#[derive(Debug, Clone, Copy)]
+pub struct World<'a> {
+    pub pixels: Pixels<'a>,
+    /// Row stride in bytes.
+    pub stride: usize,
+    /// Part of the layer the world covers.
+    pub rect: Rect,
+    pub order: ChannelOrder,
+    pub yuv: Option<YuvFormat>,
+}
+
+/// Output pixels of a render. See [`World`].
+#[derive(Debug)]
+pub struct WorldMut<'a> {
+    pub pixels: PixelsMut<'a>,
+    pub stride: usize,
+    pub rect: Rect,
+    pub order: ChannelOrder,
+    pub yuv: Option<YuvFormat>,
+}
+
+impl World<'_> {
+    pub fn depth(&self) -> Depth {
+        match self.pixels {
+            Pixels::U8(_) => Depth::U8,
+            Pixels::Ae16(_) => Depth::Ae16,
+            Pixels::F32(_) => Depth::F32,
+        }
+    }
+}
+
+impl WorldMut<'_> {
+    pub fn depth(&self) -> Depth {
+        match self.pixels {
+            PixelsMut::U8(_) => Depth::U8,
+            PixelsMut::Ae16(_) => Depth::Ae16,
+            PixelsMut::F32(_) => Depth::F32,
+        }
+    }
+}
+
+/// What a host reports for a checked out layer.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Checkout {
+    pub result_rect: Rect,
+    pub max_result_rect: Rect,
+}
+
+/// Progress reporting and cancellation during a render.
+pub trait Progress {
+    type Error;
+
+    /// Fails once the user has cancelled the render.
+    fn abort(&mut self) -> Result<(), Self::Error>;
+
+    /// Reports `done` of `total` units; fails like [`abort`](Self::abort).
+    fn progress(&mut self, done: usize, total: usize) -> Result<(), Self::Error>;
+}
+
+/// Worlds of one render.
+pub struct Worlds<'a, E> {
+    pub input: World<'a>,
+    pub output: WorldMut<'a>,
+    pub progress: &'a mut dyn Progress<Error = E>,
+}
+
+/// The host calls the effect relies on.
+pub trait Host {
+    type Error: From<HostError>;
+
+    fn is_premiere(&self) -> bool;
+
+    /// Declares a pixel format the effect accepts beyond the host's default.
+    fn add_supported_format(&mut self, format: Format) -> Result<(), Self::Error>;
+
+    /// Rect of the layer the host wants rendered.
+    fn output_request(&self) -> Rect;
+
+    /// Checks out the input layer for `request` during SmartPreRender.
+    fn checkout_layer(&mut self, request: Rect) -> Result<Checkout, Self::Error>;
+
+    /// Reports the effect's result rects from SmartPreRender.
+    fn set_result_rects(&mut self, result: Rect, max_result: Rect) -> Result<(), Self::Error>;
+
+    /// Worlds to render, `None` if the input has no pixels.
+    fn checkout_worlds(&mut self) -> Result<Option<Worlds<'_, Self::Error>>, Self::Error>;
+
+    /// Returns the input checked out by [`checkout_worlds`](Self::checkout_worlds), if it
+    /// checked one out. Called after every `checkout_worlds`, also when it returned `None` or
+    /// failed.
+    fn checkin_layer(&mut self) -> Result<(), Self::Error>;
+}
+
+/// Failures raised by the effect rather than the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostError {
+    /// The user cancelled the render.
+    Aborted,
+    /// Input and output worlds differ in depth or encoding, or use an unsupported one.
+    UnsupportedFormat { input: Format, output: Format },
+    Buffer(BufferError),
+}
+
+impl fmt::Display for HostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostError::Aborted => write!(f, "render aborted"),
+            HostError::UnsupportedFormat { input, output } => write!(f, "cannot render {input:?} into {output:?}"),
+            HostError::Buffer(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for HostError {}
+
+impl From<BufferError> for HostError {
+    fn from(e: BufferError) -> Self {
+        HostError::Buffer(e)
+    }
+}
+
+/// Rows rendered between progress reports.
+const PROGRESS_ROWS: usize = 64;
+
+/// The effect's handlers for each host command.
+#[derive(Debug, Default)]
+pub struct Effect {
+    renderer: TileRenderer,
+}
+
+impl Effect {
+    pub fn new(renderer: TileRenderer) -> Self {
+        Self { renderer }
+    }
+
+    /// GlobalSetup: declares the formats Premiere may hand over.
+    pub fn global_setup<H: Host>(&self, host: &mut H) -> Result<(), H::Error> {
+        if host.is_premiere() {
+            for format in PREMIERE_FORMATS {
+                host.add_supported_format(format)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// SmartPreRender: asks for the requested rect of the input and reports the part of it
+    /// the input covers.
+    pub fn smart_pre_render<H: Host>(&self, host: &mut H) -> Result<Checkout, H::Error> {
+        let request = host.output_request();
+        let input = host.checkout_layer(request)?;
+        let result = Checkout { result_rect: roi::result_rect(&request, &input.result_rect), max_result_rect: input.max_result_rect };
+        host.set_result_rects(result.result_rect, result.max_result_rect)?;
+        Ok(result)
+    }
+
+    /// SmartRender: renders the checked out worlds and checks the input back in, also when
+    /// the render fails or is aborted.
+    pub fn smart_render<H: Host>(&self, host: &mut H, settings: &Settings) -> Result<Option<Rect>, H::Error> {
+        let rendered = self.render(host, settings);
+        host.checkin_layer()?;
+        rendered
+    }
+
+    /// Render: the legacy, full-frame path Premiere uses. Returns the rect that was
+    /// processed; the rest of the output is cleared.
+    pub fn render<H: Host>(&self, host: &mut H, settings: &Settings) -> Result<Option<Rect>, H::Error> {
+        let Some(Worlds { input, output, progress }) = host.checkout_worlds()? else {
+            return Ok(None);
+        };
+        let (in_format, out_format) = (Format { depth: input.depth(), yuv: input.yuv }, Format { depth: output.depth(), yuv: output.yuv });
+        let unsupported = || HostError::UnsupportedFormat { input: in_format, output: out_format }.into();
+        if in_format != out_format {
+            return Err(unsupported());
+        }
+        let (src, dst) = ((input.stride, input.rect, input.order), (output.stride, output.rect, output.order));
+        let rect = match (input.pixels, output.pixels, input.yuv) {
+            (Pixels::U8(s), PixelsMut::U8(d), None) => self.render_rgb(s, src, d, dst, settings, progress)?,
+            (Pixels::Ae16(s), PixelsMut::Ae16(d), None) => self.render_rgb(s, src, d, dst, settings, progress)?,
+            (Pixels::F32(s), PixelsMut::F32(d), None) => self.render_rgb(s, src, d, dst, settings, progress)?,
+            (Pixels::U8(s), PixelsMut::U8(d), Some(format)) => self.render_yuv(s, src, d, dst, settings, format)?,
+            (Pixels::F32(s), PixelsMut::F32(d), Some(format)) => self.render_yuv(s, src, d, dst, settings, format)?,
+            _ => return Err(unsupported()),
+        };
+        Ok(Some(rect))
+    }
+
+    fn render_rgb<T, E>(&self, src: &[T], (src_stride, input, src_order): (usize, Rect, ChannelOrder), dst: &mut [T], (dst_stride, output, dst_order): (usize, Rect, ChannelOrder), settings: &Settings, progress: &mut dyn Progress<Error = E>) -> Result<Rect, E>
+    where
+        T: PixelCompute + Send + Sync,
+        E: From<HostError>,
+    {
+        let buffer = |e: BufferError| E::from(e.into());
+        let src = ImageView::new(src, input.width(), input.height(), src_stride, src_order).map_err(buffer)?;
+        let mut dst = ImageViewMut::new(dst, output.width(), output.height(), dst_stride, dst_order).map_err(buffer)?;
+        roi::render_region(&src, input, &mut dst, output, settings, |src, dst, (x, y)| {
+            let height = src.height();
+            for top in (0..height).step_by(PROGRESS_ROWS) {
+                progress.abort().map_err(RegionError::Host)?;
+                let rows = PROGRESS_ROWS.min(height - top);
+                let strip = src.crop(0, top, src.width(), rows).expect("strip lies inside the region");
+                let mut out = dst.crop_mut(0, top, dst.width(), rows).expect("strip lies inside the region");
+                self.renderer.process_at(&strip, &mut out, settings, (x, y + top))?;
+                progress.progress(top + rows, height).map_err(RegionError::Host)?;
+            }
+            Ok(())
+        }).map_err(|e: RegionError<E>| match e {
+            RegionError::Buffer(e) => buffer(e),
+            RegionError::Host(e) => e,
+        })
+    }
+
+    /// YUV worlds only come from Premiere's full-frame renders, so they skip the ROI logic.
+    fn render_yuv<T, E>(&self, src: &[T], (src_stride, input, src_order): (usize, Rect, ChannelOrder), dst: &mut [T], (dst_stride, output, dst_order): (usize, Rect, ChannelOrder), settings: &Settings, format: YuvFormat) -> Result<Rect, E>
+    where
+        T: PixelCompute + Send + Sync,
+        E: From<HostError>,
+    {
+        let buffer = |e: BufferError| E::from(e.into());
+        let rect = input.intersect(&output);
+        let (width, height) = (rect.width(), rect.height());
+        let src = ImageView::new(src, input.width(), input.height(), src_stride, src_order).map_err(buffer)?;
+        let mut dst = ImageViewMut::new(dst, output.width(), output.height(), dst_stride, dst_order).map_err(buffer)?;
+        let src = src.crop((rect.left - input.left) as usize, (rect.top - input.top) as usize, width, height);
+        let dst = dst.crop_mut((rect.left - output.left) as usize, (rect.top - output.top) as usize, width, height);
+        if let (Some(src), Some(mut dst)) = (src, dst) {
+            self.renderer.process_yuv(&src, &mut dst, settings, format).map_err(buffer)?;
+        }
+        Ok(rect)
+    }
+}
+
+/// Error of [`roi::render_region`] when the processing step can fail with a host error.
+enum RegionError<E> {
+    Buffer(BufferError),
+    Host(E),
+}
+
+impl<E> From<BufferError> for RegionError<E> {
+    fn from(e: BufferError) -> Self {
+        RegionError::Buffer(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::OnceLock;
+
+    use super::*;
+    use crate::buffer::{process_buffer, process_yuv_buffer};
+    use crate::mock::{Buffer, MockHost, MockWorld};
+    use crate::settings::{Mode, Quantize, View};
+
+    const W: usize = 96;
+    const H: usize = 150;
+
+    /// `4k.jpg` scaled to `W` x `H` inside a transparent border of 16 pixels.
+    fn fixture() -> &'static (Vec<u8>, usize, usize) {
+        static FIXTURE: OnceLock<(Vec<u8>, usize, usize)> = OnceLock::new();
+        FIXTURE.get_or_init(|| {
+            let img = image::open("../4k.jpg").unwrap().resize_exact(W as u32, H as u32, image::imageops::FilterType::Triangle).to_rgba8();
+            let (width, height) = (W + 32, H + 32);
+            let mut rgba = vec![0u8; width * height * 4];
+            for (y, row) in img.into_raw().chunks_exact(W * 4).enumerate() {
+                rgba[((y + 16) * width + 16) * 4..][..W * 4].copy_from_slice(row);
+            }
+            (rgba, width, height)
+        })
+    }
+
+    fn layer(depth: Depth, order: ChannelOrder) -> MockWorld {
+        let (rgba, width, height) = fixture();
+        MockWorld::from_rgba8(rgba, *width, *height, depth, order)
+    }
+
+    /// `process_buffer` over the whole layer, cut to `rect`.
+    fn reference(layer: &MockWorld, settings: &Settings, rect: Rect) -> Buffer {
+        fn run<T: PixelCompute>(src: &[T], layer: &MockWorld, settings: &Settings, rect: Rect) -> Vec<T> {
+            let (width, height) = (layer.rect.width(), layer.rect.height());
+            let mut full = vec![T::ZERO; src.len()];
+            let src = ImageView::packed(src, width, height, layer.order).unwrap();
+            let mut dst = ImageViewMut::packed(&mut full, width, height, layer.order).unwrap();
+            match layer.yuv {
+                Some(format) => process_yuv_buffer(&src, &mut dst, settings, format).unwrap(),
+                None => process_buffer(&src, &mut dst, settings).unwrap(),
+            }
+            let dst = ImageView::packed(&full, width, height, layer.order).unwrap();
+            let crop = dst.crop((rect.left - layer.rect.left) as usize, (rect.top - layer.rect.top) as usize, rect.width(), rect.height()).unwrap();
+            (0..crop.height()).flat_map(|y| crop.row(y).iter().copied()).collect()
+        }
+        match &layer.buffer {
+            Buffer::U8(b) => Buffer::U8(run(b, layer, settings, rect)),
+            Buffer::Ae16(b) => Buffer::Ae16(run(b, layer, settings, rect)),
+            Buffer::F32(b) => Buffer::F32(run(b, layer, settings, rect)),
+        }
+    }
+
+    #[test]
+    fn test_global_setup() {
+        let effect = Effect::default();
+        let mut ae = MockHost::new(layer(Depth::U8, ChannelOrder::Argb));
+        effect.global_setup(&mut ae).unwrap();
+        assert!(ae.formats.is_empty());
+
+        let mut premiere = MockHost::premiere(layer(Depth::U8, ChannelOrder::Bgra));
+        effect.global_setup(&mut premiere).unwrap();
+        assert_eq!(premiere.formats, PREMIERE_FORMATS);
+    }
+
+    #[test]
+    fn test_smart_pre_render() {
+        let effect = Effect::default();
+        let mut host = MockHost::new(layer(Depth::U8, ChannelOrder::Argb)).with_request(Rect::new(-10, 40, 60, 400));
+        let result = effect.smart_pre_render(&mut host).unwrap();
+        let bounds = Rect::with_size(0, 0, W + 32, H + 32);
+        assert_eq!(result, Checkout { result_rect: Rect::new(0, 40, 60, bounds.bottom), max_result_rect: bounds });
+        assert_eq!(host.result, Some(result));
+        assert_eq!(host.checkout, Some(result.result_rect));
+        assert_eq!(host.output.rect, result.result_rect);
+    }
+
+    #[test]
+    fn test_smart_render_matches_buffer() {
+        let effect = Effect::default();
+        for depth in [Depth::U8, Depth::Ae16, Depth::F32] {
+            for settings in [Settings::default(), Settings::new(Mode::Remult), Settings::default().with_quantize(Quantize::Dither)] {
+                let layer = layer(depth, ChannelOrder::Argb);
+                let request = Rect::new(5, 20, 100, 90);
+                let mut host = MockHost::new(layer.clone()).with_request(request);
+                effect.smart_pre_render(&mut host).unwrap();
+                let rendered = effect.smart_render(&mut host, &settings).unwrap();
+                assert!(rendered.is_some());
+                assert_eq!((host.checkouts, host.checkins), (1, 1));
+                assert_eq!(host.output.buffer, reference(&layer, &settings, request), "{depth:?} {settings:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_smart_render_skips_border() {
+        let effect = Effect::default();
+        let layer = layer(Depth::F32, ChannelOrder::Argb);
+        let mut host = MockHost::new(layer.clone());
+        effect.smart_pre_render(&mut host).unwrap();
+        let rendered = effect.smart_render(&mut host, &Settings::default()).unwrap();
+        assert_eq!(rendered, Some(Rect::with_size(16, 16, W, H)));
+        assert_eq!(host.output.buffer, reference(&layer, &Settings::default(), layer.rect));
+
+        // A backdrop view needs every pixel.
+        let settings = Settings::default().with_view(View::Checkerboard);
+        let mut host = MockHost::new(layer.clone());
+        effect.smart_pre_render(&mut host).unwrap();
+        assert_eq!(effect.smart_render(&mut host, &settings).unwrap(), Some(layer.rect));
+        assert_eq!(host.output.buffer, reference(&layer, &settings, layer.rect));
+    }
+
+    #[test]
+    fn test_legacy_render() {
+        let effect = Effect::default();
+        for depth in [Depth::U8, Depth::Ae16, Depth::F32] {
+            let layer = layer(depth, ChannelOrder::Bgra);
+            let mut host = MockHost::premiere(layer.clone());
+            effect.global_setup(&mut host).unwrap();
+            let settings = Settings::new(Mode::Unscreen);
+            assert_eq!(effect.render(&mut host, &settings).unwrap(), Some(Rect::with_size(16, 16, W, H)));
+            assert_eq!(host.checkins, 0);
+            assert_eq!(host.output.buffer, reference(&layer, &settings, layer.rect), "{depth:?}");
+        }
+    }
+
+    #[test]
+    fn test_legacy_render_yuv() {
+        let effect = Effect::default();
+        for format in PREMIERE_FORMATS.into_iter().filter(|f| f.yuv.is_some()) {
+            let layer = layer(format.depth, ChannelOrder::VUYA).with_yuv(format.yuv.unwrap());
+            let mut host = MockHost::premiere(layer.clone());
+            effect.render(&mut host, &Settings::default()).unwrap();
+            assert_eq!(host.output.buffer, reference(&layer, &Settings::default(), layer.rect), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn test_progress_and_abort() {
+        let effect = Effect::default();
+        let settings = Settings::new(Mode::Remult);
+        let mut host = MockHost::new(layer(Depth::U8, ChannelOrder::Argb));
+        effect.smart_pre_render(&mut host).unwrap();
+        effect.smart_render(&mut host, &settings).unwrap();
+        let total = H + 32;
+        assert_eq!(host.progress.reports, [(64, total), (128, total), (total, total)]);
+
+        let mut host = MockHost::new(layer(Depth::U8, ChannelOrder::Argb));
+        host.progress.abort_after = Some(2);
+        effect.smart_pre_render(&mut host).unwrap();
+        assert_eq!(effect.smart_render(&mut host, &settings), Err(HostError::Aborted));
+        assert_eq!(host.progress.reports, [(64, total)]);
+        assert_eq!((host.checkouts, host.checkins), (1, 1));
+    }
+
+    #[test]
+    fn test_missing_input_and_bad_formats() {
+        let effect = Effect::default();
+        let mut host = MockHost::new(layer(Depth::U8, ChannelOrder::Argb)).without_layer();
+        assert_eq!(effect.smart_pre_render(&mut host).unwrap().result_rect, Rect::default());
+        assert_eq!(effect.smart_render(&mut host, &Settings::default()), Ok(None));
+        assert_eq!((host.checkouts, host.checkins), (0, 0));
+
+        // The input is checked out before the output turns out to be missing.
+        let mut host = MockHost::new(layer(Depth::U8, ChannelOrder::Argb)).without_output();
+        effect.smart_pre_render(&mut host).unwrap();
+        assert_eq!(effect.smart_render(&mut host, &Settings::default()), Ok(None));
+        assert_eq!((host.checkouts, host.checkins), (1, 1));
+
+        let layer = layer(Depth::U8, ChannelOrder::Argb);
+        let mut host = MockHost::new(layer.clone());
+        host.output = MockWorld::blank(Depth::F32, layer.rect, ChannelOrder::Argb);
+        assert_eq!(
+            effect.render(&mut host, &Settings::default()),
+            Err(HostError::UnsupportedFormat { input: Format::rgb(Depth::U8), output: Format::rgb(Depth::F32) }),
+        );
+
+        let yuv16 = MockWorld::blank(Depth::Ae16, layer.rect, ChannelOrder::VUYA).with_yuv(YuvFormat::new(YuvMatrix::Rec709, YuvRange::Video));
+        let mut host = MockHost::premiere(yuv16);
+        assert!(matches!(effect.render(&mut host, &Settings::default()), Err(HostError::UnsupportedFormat { .. })));
+    }
+}
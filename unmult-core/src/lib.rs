@@ -0,0 +1,265 @@
+#![feature(test)]
+extern crate test;
+
+mod generated_lut;
+pub use generated_lut::{LUT, LUT_ROUND, UNSCREEN_LUT};
+
+pub mod buffer;
+pub mod host;
+pub mod mock;
+pub mod parallel;
+pub mod params;
+pub mod rgba_to_yuv;
+pub mod roi;
+pub mod sequence;
+pub mod settings;
+pub mod simd;
+
+/// 8-bit pixel with the same channel layout as the host's `PF_Pixel`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Pixel8 {
+    pub alpha: u8,
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+pub fn inner_render(pixel: &Pixel8, out_pixel: &mut Pixel8) {
+    let a = pixel.alpha;
+    let r = pixel.red;
+    let g = pixel.green;
+    let b = pixel.blue;
+
+    let max_rgb = r.max(g).max(b);
+    let offset = (max_rgb as usize) << 8;
+
+    let a = (((a as usize) * max_rgb as usize) >> 8) as u8;
+    out_pixel.alpha = a;
+    out_pixel.red   = LUT[offset + r as usize];
+    out_pixel.green = LUT[offset + g as usize];
+    out_pixel.blue  = LUT[offset + b as usize];
+}
+
+pub fn inner_render_2(pixel: &Pixel8, out_pixel: &mut Pixel8) {
+    let a = pixel.alpha;
+    let r = pixel.red;
+    let g = pixel.green;
+    let b = pixel.blue;
+
+    let max_rgb = r.max(g).max(b);
+    let offset = (max_rgb as usize) << 8;
+
+    let a = (((a as usize) * max_rgb as usize) >> 8) as u8;
+    out_pixel.alpha = a;
+    out_pixel.red   = LUT[offset + r as usize];
+    out_pixel.green = LUT[offset + g as usize];
+    out_pixel.blue  = LUT[offset + b as usize];
+}
+
+/// `inner_render` with colour and alpha rounded to nearest instead of truncated.
+pub fn inner_render_round(pixel: &Pixel8, out_pixel: &mut Pixel8) {
+    let a = pixel.alpha;
+    let r = pixel.red;
+    let g = pixel.green;
+    let b = pixel.blue;
+
+    let max_rgb = r.max(g).max(b);
+    let offset = (max_rgb as usize) << 8;
+
+    let a = (((a as usize) * max_rgb as usize * 2 + 0xFF) / (0xFF * 2)) as u8;
+    out_pixel.alpha = a;
+    out_pixel.red   = LUT_ROUND[offset + r as usize];
+    out_pixel.green = LUT_ROUND[offset + g as usize];
+    out_pixel.blue  = LUT_ROUND[offset + b as usize];
+}
+
+/// Unscreen counterpart of `inner_render`: alpha from how far the darkest channel is below white.
+pub fn unscreen_render(pixel: &Pixel8, out_pixel: &mut Pixel8) {
+    let a = pixel.alpha;
+    let r = pixel.red;
+    let g = pixel.green;
+    let b = pixel.blue;
+
+    let dark = 0xFF - r.min(g).min(b);
+    let offset = (dark as usize) << 8;
+
+    let a = (((a as usize) * dark as usize) >> 8) as u8;
+    out_pixel.alpha = a;
+    out_pixel.red   = UNSCREEN_LUT[offset + r as usize];
+    out_pixel.green = UNSCREEN_LUT[offset + g as usize];
+    out_pixel.blue  = UNSCREEN_LUT[offset + b as usize];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use buffer::ChannelOrder;
+    use rgba_to_yuv::Ae16;
+    use simd::SimdLevel;
+    use test::Bencher;
+
+    #[test]
+    fn test_inner_render_same_values() {
+        let input_pixel = Pixel8 { red: 0xFF, green: 0, blue: 0, alpha: 0x88 };
+        let mut output_pixel = Pixel8 { red: 0, green: 0, blue: 0, alpha: 0 };
+        inner_render(&input_pixel, &mut output_pixel);
+
+        let input_pixel_2 = Pixel8 { red: 0xFF, green: 0, blue: 0, alpha: 0x88 };
+        let mut output_pixel_2 = Pixel8 { red: 0, green: 0, blue: 0, alpha: 0 };
+        inner_render_2(&input_pixel_2, &mut output_pixel_2);
+
+        assert_eq!(output_pixel.alpha, output_pixel_2.alpha);
+        assert_eq!(output_pixel.red, output_pixel_2.red);
+        assert_eq!(output_pixel.green, output_pixel_2.green);
+        assert_eq!(output_pixel.blue, output_pixel_2.blue);
+    }
+
+    #[test]
+    fn test_unscreen_render_mirrors_inner_render() {
+        let invert = |p: Pixel8| Pixel8 { alpha: p.alpha, red: 0xFF - p.red, green: 0xFF - p.green, blue: 0xFF - p.blue };
+        for i in 0..=0xFFFFu32 {
+            let pixel = Pixel8 { alpha: 0xFF, red: (i >> 8) as u8, green: i as u8, blue: (i * 7) as u8 };
+            let (mut unscreened, mut unmulted) = (Pixel8::default(), Pixel8::default());
+            unscreen_render(&pixel, &mut unscreened);
+            inner_render(&invert(pixel), &mut unmulted);
+            if unmulted.alpha == 0 && unmulted == Pixel8::default() {
+                assert_eq!(unscreened, Pixel8::default(), "{pixel:?}");
+            } else {
+                assert_eq!(unscreened, invert(unmulted), "{pixel:?}");
+            }
+        }
+    }
+
+    #[bench]
+    fn bench_inner_render_jpg(b: &mut Bencher) {
+        let img = image::open("../4k.jpg").unwrap();
+        let input_pixels = img.to_rgba8().into_raw();
+        let input_pixels = input_pixels.chunks_exact(4).map(|chunk| Pixel8 {
+            red: chunk[0],
+            green: chunk[1],
+            blue: chunk[2],
+            alpha: chunk[3],
+        }).collect::<Vec<_>>();
+        let mut output_pixels = vec![Pixel8 { red: 0, green: 0, blue: 0, alpha: 0 }; 3840 * 2160];
+        b.iter(|| {
+            for (input_pixel, output_pixel) in input_pixels.iter().zip(output_pixels.iter_mut()) {
+                inner_render(input_pixel, output_pixel);
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_inner_render_2_jpg(b: &mut Bencher) {
+        let img = image::open("../4k.jpg").unwrap();
+        let input_pixels = img.to_rgba8().into_raw();
+        let input_pixels = input_pixels.chunks_exact(4).map(|chunk| Pixel8 {
+            red: chunk[0],
+            green: chunk[1],
+            blue: chunk[2],
+            alpha: chunk[3],
+        }).collect::<Vec<_>>();
+        let mut output_pixels = vec![Pixel8 { red: 0, green: 0, blue: 0, alpha: 0 }; 3840 * 2160];
+        b.iter(|| {
+            for (input_pixel, output_pixel) in input_pixels.iter().zip(output_pixels.iter_mut()) {
+                inner_render_2(input_pixel, output_pixel);
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_unmult_row_u8_jpg(b: &mut Bencher) {
+        let img = image::open("../4k.jpg").unwrap();
+        let input_pixels = img.to_rgba8().into_raw();
+        let mut output_pixels = vec![0u8; input_pixels.len()];
+        let level = SimdLevel::detect();
+        b.iter(|| simd::unmult_row_u8(level, &input_pixels, &mut output_pixels, ChannelOrder::Rgba));
+    }
+
+    #[bench]
+    fn bench_unmult_row_u8_scalar_jpg(b: &mut Bencher) {
+        let img = image::open("../4k.jpg").unwrap();
+        let input_pixels = img.to_rgba8().into_raw();
+        let mut output_pixels = vec![0u8; input_pixels.len()];
+        b.iter(|| simd::unmult_row_u8(SimdLevel::Scalar, &input_pixels, &mut output_pixels, ChannelOrder::Rgba));
+    }
+
+    #[bench]
+    fn bench_unmult_row_u16_jpg(b: &mut Bencher) {
+        let img = image::open("../4k.jpg").unwrap();
+        let input_pixels = img.to_rgba16().into_raw();
+        let mut output_pixels = vec![0u16; input_pixels.len()];
+        let level = SimdLevel::detect();
+        b.iter(|| simd::unmult_row_u16(level, &input_pixels, &mut output_pixels, ChannelOrder::Rgba));
+    }
+
+    #[bench]
+    fn bench_unmult_row_ae16_jpg(b: &mut Bencher) {
+        let img = image::open("../4k.jpg").unwrap();
+        let input_pixels: Vec<Ae16> = img.to_rgba16().into_raw().into_iter().map(|v| Ae16(((v as u32 * 32768 + 32767) / 65535) as u16)).collect();
+        let mut output_pixels = vec![Ae16(0); input_pixels.len()];
+        let level = SimdLevel::detect();
+        b.iter(|| simd::unmult_row_ae16(level, &input_pixels, &mut output_pixels, ChannelOrder::Rgba));
+    }
+
+    #[bench]
+    fn bench_unmult_row_f32_jpg(b: &mut Bencher) {
+        let img = image::open("../4k.jpg").unwrap();
+        let input_pixels = img.to_rgba32f().into_raw();
+        let mut output_pixels = vec![0.0f32; input_pixels.len()];
+        let level = SimdLevel::detect();
+        b.iter(|| simd::unmult_row_f32(level, &input_pixels, &mut output_pixels, ChannelOrder::Rgba));
+    }
+
+    #[bench]
+    fn bench_tile_renderer_u8_jpg(b: &mut Bencher) {
+        let img = image::open("../4k.jpg").unwrap();
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let input_pixels = img.to_rgba8().into_raw();
+        let mut output_pixels = vec![0u8; input_pixels.len()];
+        let renderer = parallel::TileRenderer::new();
+        let src = buffer::ImageView::packed(&input_pixels, width, height, ChannelOrder::Rgba).unwrap();
+        b.iter(|| {
+            let mut dst = buffer::ImageViewMut::packed(&mut output_pixels, width, height, ChannelOrder::Rgba).unwrap();
+            renderer.unmult(&src, &mut dst).unwrap();
+        });
+    }
+
+    #[bench]
+    #[ignore = "4k.png is not checked in"]
+    fn bench_inner_render_png(b: &mut Bencher) {
+        let img = image::open("../4k.png").unwrap();
+        let input_pixels = img.to_rgba8().into_raw();
+        let input_pixels = input_pixels.chunks_exact(4).map(|chunk| Pixel8 {
+            red: chunk[0],
+            green: chunk[1],
+            blue: chunk[2],
+            alpha: chunk[3],
+        }).collect::<Vec<_>>();
+        let mut output_pixels = vec![Pixel8 { red: 0, green: 0, blue: 0, alpha: 0 }; 3840 * 3840];
+        b.iter(|| {
+            for (input_pixel, output_pixel) in input_pixels.iter().zip(output_pixels.iter_mut()) {
+                inner_render(input_pixel, output_pixel);
+            }
+        });
+    }
+
+    #[bench]
+    #[ignore = "4k.png is not checked in"]
+    fn bench_inner_render_2_png(b: &mut Bencher) {
+        let img = image::open("../4k.png").unwrap();
+        let input_pixels = img.to_rgba8().into_raw();
+        let input_pixels = input_pixels.chunks_exact(4).map(|chunk| Pixel8 {
+            red: chunk[0],
+            green: chunk[1],
+            blue: chunk[2],
+            alpha: chunk[3],
+        }).collect::<Vec<_>>();
+        let mut output_pixels = vec![Pixel8 { red: 0, green: 0, blue: 0, alpha: 0 }; 3840 * 3840];
+        b.iter(|| {
+            for (input_pixel, output_pixel) in input_pixels.iter().zip(output_pixels.iter_mut()) {
+                inner_render_2(input_pixel, output_pixel);
+            }
+        });
+    }
+}
@@ -0,0 +1,281 @@
+//! In-memory [`Host`] that runs the effect's command flow without After Effects.
+//!
+//! [`MockHost`] mimics how AE and Premiere hand out worlds: SmartPreRender checks out the
+//! requested part of the layer, the output world is allocated at the reported result rect,
+//! and the legacy Render gets the whole layer.
+
+use crate::buffer::{convert_buffer, ChannelOrder, ImageView, ImageViewMut};
+use crate::host::{Checkout, Depth, Format, Host, HostError, Pixels, PixelsMut, Progress, World, WorldMut, Worlds};
+use crate::rgba_to_yuv::{Ae16, PixelCompute, YuvFormat};
+use crate::roi::Rect;
+use crate::settings::Quantize;
+
+/// Owned pixels of a [`MockWorld`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Buffer {
+    U8(Vec<u8>),
+    Ae16(Vec<Ae16>),
+    F32(Vec<f32>),
+}
+
+impl Buffer {
+    pub fn zeroed(depth: Depth, len: usize) -> Self {
+        match depth {
+            Depth::U8 => Buffer::U8(vec![0; len]),
+            Depth::Ae16 => Buffer::Ae16(vec![Ae16(0); len]),
+            Depth::F32 => Buffer::F32(vec![0.0; len]),
+        }
+    }
+
+    pub fn depth(&self) -> Depth {
+        match self {
+            Buffer::U8(_) => Depth::U8,
+            Buffer::Ae16(_) => Depth::Ae16,
+            Buffer::F32(_) => Depth::F32,
+        }
+    }
+
+    fn channel_size(&self) -> usize {
+        match self {
+            Buffer::U8(_) => 1,
+            Buffer::Ae16(_) => 2,
+            Buffer::F32(_) => 4,
+        }
+    }
+}
+
+/// A layer or output world owned by the mock host. Rows are packed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockWorld {
+    pub buffer: Buffer,
+    /// Part of the layer the world covers.
+    pub rect: Rect,
+    pub order: ChannelOrder,
+    pub yuv: Option<YuvFormat>,
+}
+
+impl MockWorld {
+    /// Transparent world covering `rect`.
+    pub fn blank(depth: Depth, rect: Rect, order: ChannelOrder) -> Self {
+        Self { buffer: Buffer::zeroed(depth, rect.width() * rect.height() * 4), rect, order, yuv: None }
+    }
+
+    /// World at the layer origin holding packed, `order`ed pixels.
+    pub fn new(buffer: Buffer, width: usize, height: usize, order: ChannelOrder) -> Self {
+        Self { buffer, rect: Rect::with_size(0, 0, width, height), order, yuv: None }
+    }
+
+    /// Converts packed RGBA 8-bit pixels, such as a decoded fixture image, to `depth` and `order`.
+    pub fn from_rgba8(rgba: &[u8], width: usize, height: usize, depth: Depth, order: ChannelOrder) -> Self {
+        let mut world = Self::blank(depth, Rect::with_size(0, 0, width, height), order);
+        let src = ImageView::packed(rgba, width, height, ChannelOrder::Rgba).expect("rgba matches its size");
+        match &mut world.buffer {
+            Buffer::U8(d) => convert(&src, d, width, height, order),
+            Buffer::Ae16(d) => convert(&src, d, width, height, order),
+            Buffer::F32(d) => convert(&src, d, width, height, order),
+        }
+        world
+    }
+
+    pub fn with_rect(mut self, rect: Rect) -> Self {
+        assert_eq!((rect.width(), rect.height()), (self.rect.width(), self.rect.height()), "rect must keep the world's size");
+        self.rect = rect;
+        self
+    }
+
+    pub fn with_yuv(mut self, format: YuvFormat) -> Self {
+        self.yuv = Some(format);
+        self
+    }
+
+    pub fn format(&self) -> Format {
+        Format { depth: self.buffer.depth(), yuv: self.yuv }
+    }
+
+    /// Row stride in bytes.
+    pub fn stride(&self) -> usize {
+        self.rect.width() * 4 * self.buffer.channel_size()
+    }
+
+    /// The part of the world inside `area`, which must lie within it.
+    fn view(&self, area: Rect) -> World<'_> {
+        let range = self.range(area);
+        let pixels = match &self.buffer {
+            Buffer::U8(b) => Pixels::U8(&b[range]),
+            Buffer::Ae16(b) => Pixels::Ae16(&b[range]),
+            Buffer::F32(b) => Pixels::F32(&b[range]),
+        };
+        World { pixels, stride: self.stride(), rect: area, order: self.order, yuv: self.yuv }
+    }
+
+    fn view_mut(&mut self) -> WorldMut<'_> {
+        let (stride, rect, order, yuv) = (self.stride(), self.rect, self.order, self.yuv);
+        let pixels = match &mut self.buffer {
+            Buffer::U8(b) => PixelsMut::U8(b),
+            Buffer::Ae16(b) => PixelsMut::Ae16(b),
+            Buffer::F32(b) => PixelsMut::F32(b),
+        };
+        WorldMut { pixels, stride, rect, order, yuv }
+    }
+
+    fn range(&self, area: Rect) -> std::ops::Range<usize> {
+        assert_eq!(area.intersect(&self.rect), area, "area lies outside the world");
+        if area.is_empty() {
+            return 0..0;
+        }
+        let row = self.rect.width() * 4;
+        let start = (area.top - self.rect.top) as usize * row + (area.left - self.rect.left) as usize * 4;
+        start..start + (area.height() - 1) * row + area.width() * 4
+    }
+}
+
+fn convert<D: PixelCompute>(src: &ImageView<u8>, dst: &mut [D], width: usize, height: usize, order: ChannelOrder) {
+    let mut dst = ImageViewMut::packed(dst, width, height, order).expect("world matches its size");
+    convert_buffer(src, &mut dst, Quantize::Round).expect("views have the same size");
+}
+
+/// Progress side of the mock host.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MockProgress {
+    /// Every `(done, total)` report, in order.
+    pub reports: Vec<(usize, usize)>,
+    /// Number of abort checks after which the user cancels, `None` to never cancel.
+    pub abort_after: Option<usize>,
+    checks: usize,
+}
+
+impl Progress for MockProgress {
+    type Error = HostError;
+
+    fn abort(&mut self) -> Result<(), HostError> {
+        self.checks += 1;
+        match self.abort_after {
+            Some(n) if self.checks > n => Err(HostError::Aborted),
+            _ => Ok(()),
+        }
+    }
+
+    fn progress(&mut self, done: usize, total: usize) -> Result<(), HostError> {
+        self.reports.push((done, total));
+        self.abort()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockHost {
+    pub premiere: bool,
+    /// The input layer, or `None` for a layer without pixels.
+    pub layer: Option<MockWorld>,
+    /// Rect the host asks for in SmartPreRender.
+    pub request: Rect,
+    /// The output world. Reallocated at the result rect by SmartPreRender.
+    pub output: MockWorld,
+    /// Whether the host fails to hand over the output world.
+    pub missing_output: bool,
+    /// Formats declared in GlobalSetup.
+    pub formats: Vec<Format>,
+    /// Part of the layer checked out by SmartPreRender, `None` for the whole layer.
+    pub checkout: Option<Rect>,
+    /// Result rects reported by SmartPreRender.
+    pub result: Option<Checkout>,
+    pub checkouts: usize,
+    pub checkins: usize,
+    /// Whether the input is checked out and not yet checked back in.
+    pub checked_out: bool,
+    pub progress: MockProgress,
+}
+
+impl MockHost {
+    /// After Effects host rendering `layer`, requesting all of it.
+    pub fn new(layer: MockWorld) -> Self {
+        let output = MockWorld { yuv: layer.yuv, ..MockWorld::blank(layer.buffer.depth(), layer.rect, layer.order) };
+        Self {
+            premiere: false,
+            request: layer.rect,
+            layer: Some(layer),
+            output,
+            missing_output: false,
+            formats: Vec::new(),
+            checkout: None,
+            result: None,
+            checkouts: 0,
+            checkins: 0,
+            checked_out: false,
+            progress: MockProgress::default(),
+        }
+    }
+
+    /// Premiere host rendering `layer`.
+    pub fn premiere(layer: MockWorld) -> Self {
+        Self { premiere: true, ..Self::new(layer) }
+    }
+
+    pub fn with_request(mut self, request: Rect) -> Self {
+        self.request = request;
+        self
+    }
+
+    /// Host whose layer has no pixels.
+    pub fn without_layer(mut self) -> Self {
+        self.layer = None;
+        self
+    }
+
+    /// Host that checks out the input but has no output world.
+    pub fn without_output(mut self) -> Self {
+        self.missing_output = true;
+        self
+    }
+}
+
+impl Host for MockHost {
+    type Error = HostError;
+
+    fn is_premiere(&self) -> bool {
+        self.premiere
+    }
+
+    fn add_supported_format(&mut self, format: Format) -> Result<(), HostError> {
+        self.formats.push(format);
+        Ok(())
+    }
+
+    fn output_request(&self) -> Rect {
+        self.request
+    }
+
+    fn checkout_layer(&mut self, request: Rect) -> Result<Checkout, HostError> {
+        let bounds = self.layer.as_ref().map_or(Rect::default(), |layer| layer.rect);
+        let checkout = Checkout { result_rect: request.intersect(&bounds), max_result_rect: bounds };
+        self.checkout = Some(checkout.result_rect);
+        Ok(checkout)
+    }
+
+    fn set_result_rects(&mut self, result: Rect, max_result: Rect) -> Result<(), HostError> {
+        self.result = Some(Checkout { result_rect: result, max_result_rect: max_result });
+        self.output = MockWorld { yuv: self.output.yuv, ..MockWorld::blank(self.output.buffer.depth(), result, self.output.order) };
+        Ok(())
+    }
+
+    fn checkout_worlds(&mut self) -> Result<Option<Worlds<'_, HostError>>, HostError> {
+        let Some(layer) = &self.layer else {
+            return Ok(None);
+        };
+        assert!(!self.checked_out, "input checked out twice");
+        self.checkouts += 1;
+        self.checked_out = true;
+        if self.missing_output {
+            return Ok(None);
+        }
+        let input = layer.view(self.checkout.unwrap_or(layer.rect));
+        Ok(Some(Worlds { input, output: self.output.view_mut(), progress: &mut self.progress }))
+    }
+
+    fn checkin_layer(&mut self) -> Result<(), HostError> {
+        if self.checked_out {
+            self.checked_out = false;
+            self.checkins += 1;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,283 @@
+//! Tiled, rayon-backed frame processing.
+//!
+//! Every pixel is processed independently, so the output is identical to
+//! [`process_buffer`](crate::buffer::process_buffer) for any thread count or tile size.
+
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+
+use crate::buffer::{check_size, process_span, process_yuv_span, BufferError, ImageView, ImageViewMut};
+use crate::rgba_to_yuv::{PixelCompute, YuvFormat};
+use crate::settings::{Mode, Settings};
+
+pub const DEFAULT_TILE_WIDTH: usize = 256;
+pub const DEFAULT_TILE_HEIGHT: usize = 64;
+
+#[derive(Debug)]
+pub struct TileRenderer {
+    tile_width: usize,
+    tile_height: usize,
+    pool: Option<ThreadPool>,
+}
+
+impl Default for TileRenderer {
+    fn default() -> Self {
+        Self { tile_width: DEFAULT_TILE_WIDTH, tile_height: DEFAULT_TILE_HEIGHT, pool: None }
+    }
+}
+
+impl TileRenderer {
+    /// Renderer using rayon's global pool and the default tile size.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs on a dedicated pool of `threads` workers instead of the global pool.
+    /// `0` lets rayon pick the number of threads.
+    pub fn with_threads(mut self, threads: usize) -> Result<Self, ThreadPoolBuildError> {
+        self.pool = Some(ThreadPoolBuilder::new().num_threads(threads).build()?);
+        Ok(self)
+    }
+
+    /// Sets the tile size in pixels. Zero is treated as one.
+    pub fn with_tile_size(mut self, width: usize, height: usize) -> Self {
+        self.tile_width = width.max(1);
+        self.tile_height = height.max(1);
+        self
+    }
+
+    #[inline]
+    pub fn tile_size(&self) -> (usize, usize) { (self.tile_width, self.tile_height) }
+
+    /// Number of threads work is spread across.
+    pub fn threads(&self) -> usize {
+        self.pool.as_ref().map_or_else(rayon::current_num_threads, ThreadPool::current_num_threads)
+    }
+
+    /// Runs `f` on this renderer's pool.
+    pub fn install<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+        match &self.pool {
+            Some(pool) => pool.install(f),
+            None => f(),
+        }
+    }
+
+    /// Parallel equivalent of [`unmult_buffer`](crate::buffer::unmult_buffer).
+    pub fn unmult<T>(&self, src: &ImageView<T>, dst: &mut ImageViewMut<T>) -> Result<(), BufferError>
+    where
+        T: PixelCompute + Send + Sync,
+    {
+        self.process(src, dst, &Settings::new(Mode::Unmult))
+    }
+
+    /// Parallel equivalent of [`remult_buffer`](crate::buffer::remult_buffer).
+    pub fn remult<T>(&self, src: &ImageView<T>, dst: &mut ImageViewMut<T>) -> Result<(), BufferError>
+    where
+        T: PixelCompute + Send + Sync,
+    {
+        self.process(src, dst, &Settings::new(Mode::Remult))
+    }
+
+    /// Parallel equivalent of [`unscreen_buffer`](crate::buffer::unscreen_buffer).
+    pub fn unscreen<T>(&self, src: &ImageView<T>, dst: &mut ImageViewMut<T>) -> Result<(), BufferError>
+    where
+        T: PixelCompute + Send + Sync,
+    {
+        self.process(src, dst, &Settings::new(Mode::Unscreen))
+    }
+
+    /// Parallel equivalent of [`process_buffer`](crate::buffer::process_buffer).
+    pub fn process<T>(&self, src: &ImageView<T>, dst: &mut ImageViewMut<T>, settings: &Settings) -> Result<(), BufferError>
+    where
+        T: PixelCompute + Send + Sync,
+    {
+        self.process_at(src, dst, settings, (0, 0))
+    }
+
+    /// [`process`](Self::process) for views cropped out of a larger image, whose top left
+    /// pixel sits at `origin`. Keeps position-dependent patterns such as dither aligned.
+    pub fn process_at<T>(&self, src: &ImageView<T>, dst: &mut ImageViewMut<T>, settings: &Settings, origin: (usize, usize)) -> Result<(), BufferError>
+    where
+        T: PixelCompute + Send + Sync,
+    {
+        let (src_order, dst_order) = (src.order(), dst.order());
+        self.run(src, dst, origin, |src_row, row, origin| process_span(src_row, src_order, row, dst_order, settings, origin))
+    }
+
+    /// Parallel equivalent of [`process_yuv_buffer`](crate::buffer::process_yuv_buffer).
+    pub fn process_yuv<T>(&self, src: &ImageView<T>, dst: &mut ImageViewMut<T>, settings: &Settings, format: YuvFormat) -> Result<(), BufferError>
+    where
+        T: PixelCompute + Send + Sync,
+    {
+        let (src_order, dst_order) = (src.order(), dst.order());
+        self.run(src, dst, (0, 0), |src_row, row, origin| process_yuv_span(src_row, src_order, row, dst_order, settings, format, origin))
+    }
+
+    /// Runs `span` over every tile row, with the image position of its first pixel.
+    fn run<T, F>(&self, src: &ImageView<T>, dst: &mut ImageViewMut<T>, origin: (usize, usize), span: F) -> Result<(), BufferError>
+    where
+        T: PixelCompute + Send + Sync,
+        F: Fn(&[T], &mut [T], (usize, usize)) + Sync,
+    {
+        check_size(src, dst)?;
+        let tiles = split_tiles(dst, self.tile_width, self.tile_height);
+        self.install(|| {
+            tiles.into_par_iter().for_each(|mut tile| {
+                for (i, row) in tile.rows.iter_mut().enumerate() {
+                    let x = tile.x * 4;
+                    let src_row = &src.row(tile.y + i)[x..x + row.len()];
+                    span(src_row, row, (origin.0 + tile.x, origin.1 + tile.y + i));
+                }
+            });
+        });
+        Ok(())
+    }
+}
+
+struct Tile<'a, T> {
+    x: usize,
+    y: usize,
+    rows: Vec<&'a mut [T]>,
+}
+
+/// Cuts the rows of `dst` into disjoint tiles, in row-major tile order.
+fn split_tiles<'a, T: PixelCompute>(dst: &'a mut ImageViewMut<T>, tile_width: usize, tile_height: usize) -> Vec<Tile<'a, T>> {
+    let columns = dst.width().div_ceil(tile_width);
+    let mut tiles = Vec::with_capacity(columns * dst.height().div_ceil(tile_height));
+    let mut band: Vec<Tile<T>> = Vec::new();
+    for (y, row) in dst.rows_mut().enumerate() {
+        if y % tile_height == 0 {
+            tiles.append(&mut band);
+            band = (0..columns).map(|c| Tile { x: c * tile_width, y, rows: Vec::with_capacity(tile_height) }).collect();
+        }
+        let mut rest = row;
+        for tile in band.iter_mut() {
+            let (head, tail) = rest.split_at_mut((tile_width * 4).min(rest.len()));
+            tile.rows.push(head);
+            rest = tail;
+        }
+    }
+    tiles.append(&mut band);
+    tiles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::{unmult_buffer, ChannelOrder};
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 37 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_tiles_match_serial_u8() {
+        let (width, height) = (37, 23);
+        let src = pattern(width * height * 4);
+        let mut expected = vec![0u8; src.len()];
+        unmult_buffer(
+            &ImageView::packed(&src, width, height, ChannelOrder::Rgba).unwrap(),
+            &mut ImageViewMut::packed(&mut expected, width, height, ChannelOrder::Rgba).unwrap(),
+        ).unwrap();
+
+        for threads in [1, 2, 4] {
+            for (tw, th) in [(1, 1), (7, 3), (16, 16), (64, 64), (1000, 1)] {
+                let renderer = TileRenderer::new().with_threads(threads).unwrap().with_tile_size(tw, th);
+                let mut out = vec![0u8; src.len()];
+                renderer.unmult(
+                    &ImageView::packed(&src, width, height, ChannelOrder::Rgba).unwrap(),
+                    &mut ImageViewMut::packed(&mut out, width, height, ChannelOrder::Rgba).unwrap(),
+                ).unwrap();
+                assert_eq!(out, expected, "{threads} threads, {tw}x{th} tiles");
+            }
+        }
+    }
+
+    #[test]
+    fn test_tiles_match_serial_f32_strided() {
+        let (width, height) = (19, 11);
+        let src: Vec<f32> = pattern(width * height * 4).into_iter().map(|v| v as f32 / 200.0).collect();
+        let stride = (width * 4 + 3) * 4;
+        let mut expected = vec![-1.0f32; height * (width * 4 + 3)];
+        unmult_buffer(
+            &ImageView::packed(&src, width, height, ChannelOrder::Rgba).unwrap(),
+            &mut ImageViewMut::new(&mut expected, width, height, stride, ChannelOrder::Bgra).unwrap(),
+        ).unwrap();
+
+        let renderer = TileRenderer::new().with_threads(3).unwrap().with_tile_size(5, 4);
+        let mut out = vec![-1.0f32; expected.len()];
+        renderer.unmult(
+            &ImageView::packed(&src, width, height, ChannelOrder::Rgba).unwrap(),
+            &mut ImageViewMut::new(&mut out, width, height, stride, ChannelOrder::Bgra).unwrap(),
+        ).unwrap();
+        assert_eq!(out.iter().map(|v| v.to_bits()).collect::<Vec<_>>(), expected.iter().map(|v| v.to_bits()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_tiles_match_serial_dither() {
+        let (width, height) = (29, 17);
+        let src = pattern(width * height * 4);
+        let settings = Settings::default().with_quantize(crate::settings::Quantize::Dither);
+        let mut expected = vec![0u8; src.len()];
+        crate::buffer::process_buffer(
+            &ImageView::packed(&src, width, height, ChannelOrder::Rgba).unwrap(),
+            &mut ImageViewMut::packed(&mut expected, width, height, ChannelOrder::Rgba).unwrap(),
+            &settings,
+        ).unwrap();
+
+        let renderer = TileRenderer::new().with_threads(3).unwrap().with_tile_size(5, 3);
+        let mut out = vec![0u8; src.len()];
+        renderer.process(
+            &ImageView::packed(&src, width, height, ChannelOrder::Rgba).unwrap(),
+            &mut ImageViewMut::packed(&mut out, width, height, ChannelOrder::Rgba).unwrap(),
+            &settings,
+        ).unwrap();
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_tiles_match_serial_yuv() {
+        use crate::buffer::process_yuv_buffer;
+        use crate::rgba_to_yuv::{YuvMatrix, YuvRange};
+
+        let (width, height) = (23, 9);
+        let src = pattern(width * height * 4);
+        let format = YuvFormat::new(YuvMatrix::Rec601, YuvRange::Video);
+        let settings = Settings::default();
+        let mut expected = vec![0u8; src.len()];
+        process_yuv_buffer(
+            &ImageView::packed(&src, width, height, ChannelOrder::VUYA).unwrap(),
+            &mut ImageViewMut::packed(&mut expected, width, height, ChannelOrder::VUYA).unwrap(),
+            &settings,
+            format,
+        ).unwrap();
+
+        let renderer = TileRenderer::new().with_threads(2).unwrap().with_tile_size(4, 4);
+        let mut out = vec![0u8; src.len()];
+        renderer.process_yuv(
+            &ImageView::packed(&src, width, height, ChannelOrder::VUYA).unwrap(),
+            &mut ImageViewMut::packed(&mut out, width, height, ChannelOrder::VUYA).unwrap(),
+            &settings,
+            format,
+        ).unwrap();
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_empty_and_mismatched() {
+        let renderer = TileRenderer::new();
+        let mut out: [u8; 0] = [];
+        renderer.unmult(
+            &ImageView::packed(&[], 0, 0, ChannelOrder::Rgba).unwrap(),
+            &mut ImageViewMut::packed(&mut out, 0, 0, ChannelOrder::Rgba).unwrap(),
+        ).unwrap();
+
+        let src = [0u8; 8];
+        let mut out = [0u8; 8];
+        let err = renderer.unmult(
+            &ImageView::packed(&src, 2, 1, ChannelOrder::Rgba).unwrap(),
+            &mut ImageViewMut::packed(&mut out, 1, 2, ChannelOrder::Rgba).unwrap(),
+        ).unwrap_err();
+        assert_eq!(err, BufferError::SizeMismatch { src: (2, 1), dst: (1, 2) });
+    }
+}
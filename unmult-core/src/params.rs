@@ -0,0 +1,278 @@
+//! Effect parameters, described once for every host.
+//!
+//! Each [`ParamDef`] in [`PARAMS`] names a control, its kind and default, and maps onto a
+//! field of [`Settings`]. Hosts build their controls from the table and feed the values
+//! back through [`Settings::set`].
+
+use crate::settings::{AlphaCombine, AlphaSource, Association, Background, Hdr, Levels, Mode, Quantize, Settings, Transfer, View};
+
+/// A settings enum offered as a popup, listed in `ALL` order.
+pub trait Choice: Copy + Default + PartialEq + 'static {
+    const ALL: &'static [Self];
+    const LABELS: &'static [&'static str];
+    /// Short lowercase names for command lines and files.
+    const KEYS: &'static [&'static str];
+
+    #[inline]
+    fn index(self) -> usize {
+        Self::ALL.iter().position(|&c| c == self).unwrap_or(0)
+    }
+
+    /// Choice at `index`, the default when out of range.
+    #[inline]
+    fn from_index(index: usize) -> Self {
+        Self::ALL.get(index).copied().unwrap_or_default()
+    }
+}
+
+macro_rules! choice {
+    ($ty:ty, [$($label:expr => $key:expr),* $(,)?]) => {
+        impl Choice for $ty {
+            const ALL: &'static [Self] = &<$ty>::ALL;
+            const LABELS: &'static [&'static str] = &[$($label),*];
+            const KEYS: &'static [&'static str] = &[$($key),*];
+        }
+    };
+}
+
+choice!(Mode, ["Unmult" => "unmult", "Remult" => "remult", "Unscreen" => "unscreen"]);
+choice!(AlphaSource, [
+    "Max" => "max",
+    "Luminance (Rec.709)" => "rec709",
+    "Luminance (Rec.601)" => "rec601",
+    "Luminance (Rec.2020)" => "rec2020",
+    "Average" => "average",
+    "Min" => "min",
+    "Red" => "red",
+    "Green" => "green",
+    "Blue" => "blue",
+]);
+choice!(AlphaCombine, [
+    "Legacy" => "legacy",
+    "Replace" => "replace",
+    "Multiply" => "multiply",
+    "Min" => "min",
+    "Max" => "max",
+    "Ignore" => "ignore",
+]);
+choice!(Transfer, [
+    "Linear / As Is" => "linear",
+    "sRGB" => "srgb",
+    "Rec.709" => "rec709",
+    "Gamma 2.2" => "gamma2.2",
+    "Gamma 2.4" => "gamma2.4",
+]);
+choice!(Hdr, [
+    "Pass Through" => "passthrough",
+    "Clamp Alpha" => "clamp",
+    "Soft Knee" => "knee",
+    "Preserve Energy" => "preserve",
+]);
+choice!(Quantize, ["Truncate (Legacy)" => "truncate", "Round" => "round", "Dither" => "dither"]);
+choice!(Association, ["Straight" => "straight", "Premultiplied" => "premultiplied"]);
+choice!(View, [
+    "Final" => "final",
+    "Alpha" => "alpha",
+    "Checkerboard" => "checkerboard",
+    "Solid Colour" => "solid",
+    "Diagnostic" => "diagnostic",
+]);
+
+/// Identifies a control. Also the plugin's parameter key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ParamId {
+    Mode,
+    AlphaSource,
+    AlphaCombine,
+    Background,
+    Transfer,
+    Hdr,
+    BlackPoint,
+    WhitePoint,
+    Quantize,
+    Output,
+    View,
+    ViewColour,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParamKind {
+    /// Index into `options`, which `keys` name for command lines and files.
+    Popup { options: &'static [&'static str], keys: &'static [&'static str], default: usize },
+    /// `0..1`, shown as a percentage.
+    Percent { default: f32 },
+    Colour { default: Background },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParamDef {
+    pub id: ParamId,
+    pub name: &'static str,
+    pub kind: ParamKind,
+}
+
+impl ParamDef {
+    pub fn default_value(&self) -> ParamValue {
+        match self.kind {
+            ParamKind::Popup { default, .. } => ParamValue::Popup(default),
+            ParamKind::Percent { default } => ParamValue::Percent(default),
+            ParamKind::Colour { default } => ParamValue::Colour(default),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParamValue {
+    /// 0-based option index.
+    Popup(usize),
+    Percent(f32),
+    Colour(Background),
+}
+
+const fn popup<C: Choice>(id: ParamId, name: &'static str) -> ParamDef {
+    ParamDef { id, name, kind: ParamKind::Popup { options: C::LABELS, keys: C::KEYS, default: 0 } }
+}
+
+/// Every control, in display order. Defaults reproduce `Settings::default()`.
+pub const PARAMS: &[ParamDef] = &[
+    popup::<Mode>(ParamId::Mode, "Mode"),
+    popup::<AlphaSource>(ParamId::AlphaSource, "Alpha Source"),
+    popup::<AlphaCombine>(ParamId::AlphaCombine, "Input Alpha"),
+    ParamDef { id: ParamId::Background, name: "Background", kind: ParamKind::Colour { default: Background::BLACK } },
+    popup::<Transfer>(ParamId::Transfer, "Input Transfer"),
+    popup::<Hdr>(ParamId::Hdr, "Superwhites"),
+    ParamDef { id: ParamId::BlackPoint, name: "Black Point", kind: ParamKind::Percent { default: 0.0 } },
+    ParamDef { id: ParamId::WhitePoint, name: "White Point", kind: ParamKind::Percent { default: 1.0 } },
+    popup::<Quantize>(ParamId::Quantize, "Quantization"),
+    popup::<Association>(ParamId::Output, "Output"),
+    popup::<View>(ParamId::View, "View"),
+    ParamDef { id: ParamId::ViewColour, name: "View Colour", kind: ParamKind::Colour { default: Background::BLACK } },
+];
+
+impl Settings {
+    /// Settings read from a host through `value`, called once per entry of [`PARAMS`].
+    pub fn from_params<E>(mut value: impl FnMut(&ParamDef) -> Result<ParamValue, E>) -> Result<Settings, E> {
+        let mut settings = Settings::default();
+        for def in PARAMS {
+            settings.set(def.id, value(def)?);
+        }
+        Ok(settings)
+    }
+
+    /// Stores `value` in the field behind `id`. Values of the wrong kind are ignored.
+    pub fn set(&mut self, id: ParamId, value: ParamValue) {
+        match (id, value) {
+            (ParamId::Mode, ParamValue::Popup(i)) => self.mode = Choice::from_index(i),
+            (ParamId::AlphaSource, ParamValue::Popup(i)) => self.alpha_source = Choice::from_index(i),
+            (ParamId::AlphaCombine, ParamValue::Popup(i)) => self.alpha_combine = Choice::from_index(i),
+            (ParamId::Background, ParamValue::Colour(c)) => self.background = c,
+            (ParamId::Transfer, ParamValue::Popup(i)) => self.transfer = Choice::from_index(i),
+            (ParamId::Hdr, ParamValue::Popup(i)) => self.hdr = Choice::from_index(i),
+            (ParamId::BlackPoint, ParamValue::Percent(v)) => self.levels = Levels::new(v, self.levels.white),
+            (ParamId::WhitePoint, ParamValue::Percent(v)) => self.levels = Levels::new(self.levels.black, v),
+            (ParamId::Quantize, ParamValue::Popup(i)) => self.quantize = Choice::from_index(i),
+            (ParamId::Output, ParamValue::Popup(i)) => self.association = Choice::from_index(i),
+            (ParamId::View, ParamValue::Popup(i)) => self.view = Choice::from_index(i),
+            (ParamId::ViewColour, ParamValue::Colour(c)) => self.view_colour = c,
+            _ => {}
+        }
+    }
+
+    /// Current value of the field behind `id`.
+    pub fn get(&self, id: ParamId) -> ParamValue {
+        match id {
+            ParamId::Mode => ParamValue::Popup(self.mode.index()),
+            ParamId::AlphaSource => ParamValue::Popup(self.alpha_source.index()),
+            ParamId::AlphaCombine => ParamValue::Popup(self.alpha_combine.index()),
+            ParamId::Background => ParamValue::Colour(self.background),
+            ParamId::Transfer => ParamValue::Popup(self.transfer.index()),
+            ParamId::Hdr => ParamValue::Popup(self.hdr.index()),
+            ParamId::BlackPoint => ParamValue::Percent(self.levels.black),
+            ParamId::WhitePoint => ParamValue::Percent(self.levels.white),
+            ParamId::Quantize => ParamValue::Popup(self.quantize.index()),
+            ParamId::Output => ParamValue::Popup(self.association.index()),
+            ParamId::View => ParamValue::Popup(self.view.index()),
+            ParamId::ViewColour => ParamValue::Colour(self.view_colour),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_defaults_match_settings() {
+        let settings = Settings::from_params::<()>(|def| Ok(def.default_value())).unwrap();
+        assert_eq!(settings, Settings::default());
+        for def in PARAMS {
+            assert_eq!(Settings::default().get(def.id), def.default_value(), "{}", def.name);
+        }
+    }
+
+    #[test]
+    fn test_every_option_round_trips() {
+        let ids: HashSet<ParamId> = PARAMS.iter().map(|def| def.id).collect();
+        assert_eq!(ids.len(), PARAMS.len(), "duplicate parameter");
+
+        for def in PARAMS {
+            let values = match def.kind {
+                ParamKind::Popup { options, .. } => (0..options.len()).map(ParamValue::Popup).collect(),
+                ParamKind::Percent { .. } => vec![ParamValue::Percent(0.25), ParamValue::Percent(0.75)],
+                ParamKind::Colour { .. } => vec![ParamValue::Colour(Background::new(0.1, 0.2, 0.3))],
+            };
+            for value in values {
+                let mut settings = Settings::default();
+                settings.set(def.id, value);
+                assert_eq!(settings.get(def.id), value, "{}", def.name);
+                if value != def.default_value() {
+                    assert_ne!(settings, Settings::default(), "{} {value:?} has no effect", def.name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_labels_cover_choices() {
+        fn check<C: Choice + std::fmt::Debug>() {
+            assert_eq!(C::ALL.len(), C::LABELS.len());
+            assert_eq!(C::ALL.len(), C::KEYS.len());
+            for (i, key) in C::KEYS.iter().enumerate() {
+                assert!(!C::KEYS[..i].contains(key), "duplicate key {key}");
+                assert!(key.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '.'), "{key}");
+            }
+            assert_eq!(C::ALL[0], C::default());
+            for (i, &c) in C::ALL.iter().enumerate() {
+                assert_eq!(c.index(), i);
+                assert_eq!(C::from_index(i), c);
+            }
+            assert_eq!(C::from_index(C::ALL.len()), C::default());
+        }
+        check::<Mode>();
+        check::<AlphaSource>();
+        check::<AlphaCombine>();
+        check::<Transfer>();
+        check::<Hdr>();
+        check::<Quantize>();
+        check::<Association>();
+        check::<View>();
+    }
+
+    #[test]
+    fn test_from_params_reads_host_values() {
+        let settings = Settings::from_params::<()>(|def| {
+            Ok(match def.id {
+                ParamId::Mode => ParamValue::Popup(2),
+                ParamId::BlackPoint => ParamValue::Percent(0.1),
+                _ => def.default_value(),
+            })
+        })
+        .unwrap();
+        assert_eq!(settings.mode, Mode::Unscreen);
+        assert_eq!(settings.levels, Levels::new(0.1, 1.0));
+
+        let err = Settings::from_params(|def| if def.id == ParamId::Hdr { Err("missing") } else { Ok(def.default_value()) });
+        assert_eq!(err, Err("missing"));
+    }
+}
@@ -0,0 +1,1351 @@
+use half::f16;
+use num_traits::AsPrimitive;
+
+use crate::buffer::ChannelOrder;
+use crate::settings::{AlphaCombine, AlphaSource, Association, Background, Hdr, Mode, Quantize, Settings, Transfer, View};
+use crate::simd::{self, SimdLevel};
+use crate::{inner_render, inner_render_round, unscreen_render, Pixel8, LUT};
+
+pub trait PixelCompute: Copy + PartialEq + AsPrimitive<f32> + std::fmt::Debug {
+    const ZERO: Self;
+    /// Channel value of full intensity, the host's white level for this depth.
+    const SCALE: f32;
+    fn to_f32(self) -> f32;
+    fn from_f32(val: f32) -> Self;
+
+    /// `from_f32` with `offset` code values added before truncating; see `Quantize::offset`.
+    #[inline]
+    fn quantize(val: f32, offset: f32) -> Self { Self::from_f32(val + offset / Self::SCALE) }
+
+    /// Round-to-nearest unmult through a table, where this depth has one for `settings`.
+    #[inline]
+    fn unmult_rounded(_pixel: &RgbaPixel<Self>, _settings: &Settings) -> Option<RgbaPixel<Self>> { None }
+
+    /// Unmultiplies `pixel` using the fastest path available for this depth.
+    #[inline]
+    fn unmult(pixel: &RgbaPixel<Self>, settings: &Settings) -> RgbaPixel<Self> { pixel.unmult_rgba_with(settings) }
+
+    /// Unscreens `pixel` using the fastest path available for this depth.
+    #[inline]
+    fn unscreen(pixel: &RgbaPixel<Self>, settings: &Settings) -> RgbaPixel<Self> { pixel.unscreen_rgba_with(settings) }
+
+    /// Unmultiplies a packed row of pixels sharing one channel order.
+    fn unmult_row(src: &[Self], dst: &mut [Self], order: ChannelOrder) { simd::unmult_row_scalar(src, dst, order) }
+}
+
+impl PixelCompute for u8 {
+    const ZERO: Self = 0;
+    const SCALE: f32 = 255.0;
+    fn to_f32(self) -> f32 { (self as f32) / Self::SCALE }
+    fn from_f32(val: f32) -> Self { (val * Self::SCALE) as u8 }
+    fn quantize(val: f32, offset: f32) -> Self { (val * Self::SCALE + offset) as u8 }
+
+    #[inline]
+    fn unmult_rounded(pixel: &RgbaPixel<Self>, settings: &Settings) -> Option<RgbaPixel<Self>> {
+        // The table is the Legacy formula, which keeps colour at zero input alpha.
+        if settings.mode != Mode::Unmult || settings.transfer != Transfer::Linear || settings.alpha_combine != AlphaCombine::Legacy || !settings.is_max_matte() {
+            return None;
+        }
+        let mut out = Pixel8::default();
+        inner_render_round(&Pixel8 { alpha: pixel.alpha, red: pixel.red, green: pixel.green, blue: pixel.blue }, &mut out);
+        Some(RgbaPixel::new(out.red, out.green, out.blue, out.alpha))
+    }
+
+    #[inline]
+    fn unmult(pixel: &RgbaPixel<Self>, settings: &Settings) -> RgbaPixel<Self> {
+        let combine = settings.alpha_combine;
+        // The tables divide the colour as is, so associating with the input alpha, a
+        // background or an HDR roll-off needs the float path.
+        if combine == AlphaCombine::Replace || !settings.background.is_black() || settings.hdr != Hdr::Passthrough {
+            return pixel.unmult_rgba_with(settings);
+        }
+        if settings.is_max_matte() && combine == AlphaCombine::Legacy {
+            let mut out = Pixel8::default();
+            inner_render(&Pixel8 { alpha: pixel.alpha, red: pixel.red, green: pixel.green, blue: pixel.blue }, &mut out);
+            return RgbaPixel::new(out.red, out.green, out.blue, out.alpha);
+        }
+        if pixel.alpha == 0 && combine != AlphaCombine::Legacy && combine.zero_input_is_transparent() {
+            return RgbaPixel::zero();
+        }
+        // Same LUT as `inner_render`, indexed by the shaped matte instead of the raw max.
+        let source = settings.alpha_source.matte(pixel.red.to_f32(), pixel.green.to_f32(), pixel.blue.to_f32());
+        let matte = (settings.levels.apply(source) * Self::SCALE).round() as u8;
+        let offset = (matte as usize) << 8;
+        let alpha = match combine {
+            AlphaCombine::Legacy => (((pixel.alpha as usize) * matte as usize) >> 8) as u8,
+            _ => Self::from_f32(combine.apply(matte.to_f32(), pixel.alpha.to_f32())),
+        };
+        RgbaPixel::new(
+            LUT[offset + pixel.red as usize],
+            LUT[offset + pixel.green as usize],
+            LUT[offset + pixel.blue as usize],
+            alpha,
+        )
+    }
+
+    #[inline]
+    fn unscreen(pixel: &RgbaPixel<Self>, settings: &Settings) -> RgbaPixel<Self> {
+        if settings.alpha_source != AlphaSource::Max || !settings.levels.is_identity() || settings.alpha_combine != AlphaCombine::Legacy {
+            return pixel.unscreen_rgba_with(settings);
+        }
+        let mut out = Pixel8::default();
+        unscreen_render(&Pixel8 { alpha: pixel.alpha, red: pixel.red, green: pixel.green, blue: pixel.blue }, &mut out);
+        RgbaPixel::new(out.red, out.green, out.blue, out.alpha)
+    }
+
+    fn unmult_row(src: &[Self], dst: &mut [Self], order: ChannelOrder) { simd::unmult_row_u8(SimdLevel::detect(), src, dst, order) }
+}
+
+impl PixelCompute for u16 {
+    const ZERO: Self = 0;
+    const SCALE: f32 = 65535.0;
+    fn to_f32(self) -> f32 { (self as f32) / Self::SCALE }
+    fn from_f32(val: f32) -> Self { (val * Self::SCALE) as u16 }
+    fn quantize(val: f32, offset: f32) -> Self { (val * Self::SCALE + offset) as u16 }
+    fn unmult_row(src: &[Self], dst: &mut [Self], order: ChannelOrder) { simd::unmult_row_u16(SimdLevel::detect(), src, dst, order) }
+}
+
+/// After Effects / Premiere 16bpc channel, where white is 32768 rather than `u16::MAX`.
+///
+/// Use plain `u16` for full-range 16-bit data such as image files.
+#[repr(transparent)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ae16(pub u16);
+
+impl Ae16 {
+    pub const WHITE: u16 = 32768;
+}
+
+impl AsPrimitive<f32> for Ae16 {
+    #[inline]
+    fn as_(self) -> f32 { self.0 as f32 }
+}
+
+impl PixelCompute for Ae16 {
+    const ZERO: Self = Ae16(0);
+    const SCALE: f32 = Ae16::WHITE as f32;
+    fn to_f32(self) -> f32 { (self.0 as f32) / Self::SCALE }
+    fn from_f32(val: f32) -> Self { Ae16((val * Self::SCALE).min(Self::SCALE) as u16) }
+    fn quantize(val: f32, offset: f32) -> Self { Ae16((val * Self::SCALE + offset).min(Self::SCALE) as u16) }
+    fn unmult_row(src: &[Self], dst: &mut [Self], order: ChannelOrder) { simd::unmult_row_ae16(SimdLevel::detect(), src, dst, order) }
+}
+
+/// Half float, as stored in EXR files and GPU textures. Computed through `f32`.
+impl PixelCompute for f16 {
+    const ZERO: Self = f16::ZERO;
+    const SCALE: f32 = 1.0;
+    fn to_f32(self) -> f32 { f16::to_f32(self) }
+    fn from_f32(val: f32) -> Self { f16::from_f32(val) }
+    fn quantize(val: f32, _offset: f32) -> Self { f16::from_f32(val) }
+}
+
+/// Double precision. Plain unmult runs at full precision, shaped mattes through `f32`.
+impl PixelCompute for f64 {
+    const ZERO: Self = 0.0;
+    const SCALE: f32 = 1.0;
+    fn to_f32(self) -> f32 { self as f32 }
+    fn from_f32(val: f32) -> Self { val as f64 }
+    fn quantize(val: f32, _offset: f32) -> Self { val as f64 }
+
+    #[inline]
+    fn unmult(pixel: &RgbaPixel<Self>, settings: &Settings) -> RgbaPixel<Self> {
+        if !settings.is_max_matte() || settings.alpha_combine != AlphaCombine::Legacy {
+            return pixel.unmult_rgba_with(settings);
+        }
+        let (mut r, mut g, mut b, a) = (pixel.red, pixel.green, pixel.blue, pixel.alpha);
+        if a == 0.0 {
+            return RgbaPixel::zero();
+        }
+        if a < 1.0 {
+            r *= a;
+            g *= a;
+            b *= a;
+        }
+        let max_val = max3(r, g, b);
+        if max_val > 0.0 {
+            let scale = 1.0 / max_val;
+            RgbaPixel::new(r * scale, g * scale, b * scale, max_val)
+        } else {
+            RgbaPixel::zero()
+        }
+    }
+}
+
+impl PixelCompute for f32 {
+    const ZERO: Self = 0.0;
+    const SCALE: f32 = 1.0;
+    fn to_f32(self) -> f32 { self }
+    fn from_f32(val: f32) -> Self { val }
+    fn quantize(val: f32, _offset: f32) -> Self { val }
+    fn unmult_row(src: &[Self], dst: &mut [Self], order: ChannelOrder) { simd::unmult_row_f32(SimdLevel::detect(), src, dst, order) }
+}
+
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct RgbaPixel<T: PixelCompute> {
+    red: T,
+    green: T,
+    blue: T,
+    alpha: T,
+}
+
+impl<T> RgbaPixel<T> where T: PixelCompute {
+    pub fn new(r: T, g: T, b: T, a: T) -> Self {
+        Self { red: r, green: g, blue: b, alpha: a }
+    }
+
+    #[inline]
+    pub fn get_red(&self) -> T { self.red }
+    #[inline]
+    pub fn get_green(&self) -> T { self.green }
+    #[inline]
+    pub fn get_blue(&self) -> T { self.blue }
+    #[inline]
+    pub fn get_alpha(&self) -> T { self.alpha }
+
+    pub fn zero() -> Self {
+        Self { red: T::ZERO, green: T::ZERO, blue: T::ZERO, alpha: T::ZERO }
+    }
+
+    pub fn unmult_rgba(&self) -> RgbaPixel<T> {
+        self.unmult_rgba_with(&Settings::default())
+    }
+
+    /// `unmult_rgba` with the alpha shaping and background from `settings` applied.
+    ///
+    /// Over a background `B` the input is read as `F * alpha + B * (1 - alpha)`; over black this
+    /// is the plain `F * alpha` and the result is exactly that of `unmult_rgba`.
+    pub fn unmult_rgba_with(&self, settings: &Settings) -> RgbaPixel<T> {
+        let r = self.get_red();
+        let g = self.get_green();
+        let b = self.get_blue();
+        let a = self.get_alpha();
+        let combine = settings.alpha_combine;
+        if a == T::ZERO && combine.zero_input_is_transparent() {
+            return RgbaPixel::zero();
+        }
+        let a_f = a.to_f32();
+        let mut r_f = r.to_f32();
+        let mut g_f = g.to_f32();
+        let mut b_f = b.to_f32();
+
+        if combine.associates_input() && a_f < T::SCALE {
+            r_f *= a_f;
+            g_f *= a_f;
+            b_f *= a_f;
+        }
+
+        let bg = settings.background;
+        if bg.is_black() {
+            let max_val = settings.levels.apply(settings.alpha_source.matte(r_f, g_f, b_f));
+            let (alpha, divisor) = settings.hdr.resolve(max_val);
+            if max_val > 0.0 {
+                let scale = 1.0 / divisor;
+                r_f *= scale;
+                g_f *= scale;
+                b_f *= scale;
+            } else {
+                return Self::transparent(combine, a_f);
+            }
+            return RgbaPixel::new(T::from_f32(r_f), T::from_f32(g_f), T::from_f32(b_f), T::from_f32(combine.apply(alpha, a_f)));
+        }
+
+        let matte = settings.levels.apply(settings.alpha_source.matte(
+            Background::distance(r_f, bg.red),
+            Background::distance(g_f, bg.green),
+            Background::distance(b_f, bg.blue),
+        ));
+        if matte.is_nan() || matte <= 0.0 {
+            return Self::transparent(combine, a_f);
+        }
+        let (alpha, divisor) = settings.hdr.resolve(matte);
+        let scale = 1.0 / divisor;
+        RgbaPixel::new(
+            T::from_f32(bg.red + (r_f - bg.red) * scale),
+            T::from_f32(bg.green + (g_f - bg.green) * scale),
+            T::from_f32(bg.blue + (b_f - bg.blue) * scale),
+            T::from_f32(combine.apply(alpha, a_f)),
+        )
+    }
+
+    /// Result for an empty matte: black, with whatever alpha `combine` keeps from the input.
+    #[inline]
+    fn transparent(combine: AlphaCombine, input: f32) -> RgbaPixel<T> {
+        RgbaPixel::new(T::ZERO, T::ZERO, T::ZERO, T::from_f32(combine.apply(0.0, input)))
+    }
+
+    pub fn unscreen_rgba(&self) -> RgbaPixel<T> {
+        self.unscreen_rgba_with(&Settings::default())
+    }
+
+    /// Mirror of `unmult_rgba` around white: alpha comes from how far the colour is below
+    /// white, and the straight colour reproduces the input when multiplied over white.
+    /// The alpha source and levels in `settings` shape that darkness; the background is ignored.
+    pub fn unscreen_rgba_with(&self, settings: &Settings) -> RgbaPixel<T> {
+        let a = self.get_alpha();
+        let combine = settings.alpha_combine;
+        if a == T::ZERO && combine.zero_input_is_transparent() {
+            return RgbaPixel::zero();
+        }
+        let a_f = a.to_f32();
+        let mut r_f = 1.0 - self.get_red().to_f32();
+        let mut g_f = 1.0 - self.get_green().to_f32();
+        let mut b_f = 1.0 - self.get_blue().to_f32();
+
+        if combine.associates_input() && a_f < T::SCALE {
+            r_f *= a_f;
+            g_f *= a_f;
+            b_f *= a_f;
+        }
+
+        let matte = settings.levels.apply(settings.alpha_source.matte(r_f, g_f, b_f));
+        if matte.is_nan() || matte <= 0.0 {
+            return Self::transparent(combine, a_f);
+        }
+        let scale = 1.0 / matte;
+        RgbaPixel::new(
+            T::from_f32(1.0 - (r_f * scale).min(1.0)),
+            T::from_f32(1.0 - (g_f * scale).min(1.0)),
+            T::from_f32(1.0 - (b_f * scale).min(1.0)),
+            T::from_f32(combine.apply(matte, a_f)),
+        )
+    }
+
+    /// Multiplies straight colour over white, rebuilding the image `unscreen_rgba` started from.
+    pub fn multiply_over_white(&self) -> RgbaPixel<T> {
+        let a_f = self.get_alpha().to_f32();
+        let over = |c: T| T::from_f32(1.0 - a_f * (1.0 - c.to_f32()));
+        RgbaPixel::new(over(self.get_red()), over(self.get_green()), over(self.get_blue()), T::from_f32(1.0))
+    }
+
+    /// Colour multiplied by alpha, alpha unchanged.
+    pub fn premultiplied(&self) -> RgbaPixel<T> {
+        let a_f = self.get_alpha().to_f32();
+        RgbaPixel::new(
+            T::from_f32(self.get_red().to_f32() * a_f),
+            T::from_f32(self.get_green().to_f32() * a_f),
+            T::from_f32(self.get_blue().to_f32() * a_f),
+            self.get_alpha(),
+        )
+    }
+
+    /// Composites straight colour over black, rebuilding the opaque image `unmult_rgba` started from.
+    pub fn remult_rgba(&self) -> RgbaPixel<T> {
+        self.remult_rgba_over(Background::BLACK)
+    }
+
+    /// Composites straight colour over `background`, the inverse of unmultiplying against it.
+    pub fn remult_rgba_over(&self, background: Background) -> RgbaPixel<T> {
+        let a_f = self.get_alpha().to_f32();
+        let over = |c: T, bg: f32| T::from_f32(c.to_f32() * a_f + bg * (1.0 - a_f));
+        RgbaPixel::new(
+            over(self.get_red(), background.red),
+            over(self.get_green(), background.green),
+            over(self.get_blue(), background.blue),
+            T::from_f32(1.0),
+        )
+    }
+
+    #[inline]
+    pub fn apply(&self, settings: &Settings) -> RgbaPixel<T> {
+        self.apply_at(settings, 0, 0)
+    }
+
+    /// `apply` for the pixel at `x`, `y`, which positions the dither pattern.
+    #[inline]
+    pub fn apply_at(&self, settings: &Settings, x: usize, y: usize) -> RgbaPixel<T> {
+        if settings.association == Association::Premultiplied || settings.view != View::Final {
+            return self.apply_float(settings, x, y);
+        }
+        if settings.quantize != Quantize::Truncate {
+            if settings.quantize == Quantize::Round {
+                if let Some(out) = T::unmult_rounded(self, settings) {
+                    return out;
+                }
+            }
+            return self.apply_float(settings, x, y);
+        }
+        if settings.transfer != Transfer::Linear {
+            return self.apply_float(settings, x, y);
+        }
+        match settings.mode {
+            Mode::Unmult => T::unmult(self, settings),
+            Mode::Remult => self.remult_rgba_over(settings.background),
+            Mode::Unscreen => T::unscreen(self, settings),
+        }
+    }
+}
+
+impl<T> RgbaPixel<T> where T: PixelCompute {
+    /// `apply` through `f32` for the pixel at `x`, `y`: decodes the colour and background
+    /// with `settings.transfer`, runs the operation in linear light, renders the view or
+    /// premultiplies, and re-encodes quantising with `settings.quantize`.
+    fn apply_float(&self, settings: &Settings, x: usize, y: usize) -> RgbaPixel<T> {
+        let transfer = settings.transfer;
+        let bg = settings.background;
+        let offset = settings.quantize.offset(x, y);
+        let linear_settings = Settings {
+            transfer: Transfer::Linear,
+            quantize: Quantize::Truncate,
+            association: Association::Straight,
+            view: View::Final,
+            background: Background::new(transfer.decode(bg.red), transfer.decode(bg.green), transfer.decode(bg.blue)),
+            ..*settings
+        };
+        let linear = RgbaPixel::<f32>::new(
+            transfer.decode(self.get_red().to_f32()),
+            transfer.decode(self.get_green().to_f32()),
+            transfer.decode(self.get_blue().to_f32()),
+            self.get_alpha().to_f32(),
+        );
+        let mut out = linear.apply(&linear_settings);
+        if settings.view != View::Final {
+            let colour = settings.view_colour;
+            let colour = Background::new(transfer.decode(colour.red), transfer.decode(colour.green), transfer.decode(colour.blue));
+            out = out.view(settings.view, colour, x, y);
+        } else if settings.association == Association::Premultiplied {
+            out = out.premultiplied();
+        }
+        RgbaPixel::new(
+            T::quantize(transfer.encode(out.get_red()), offset),
+            T::quantize(transfer.encode(out.get_green()), offset),
+            T::quantize(transfer.encode(out.get_blue()), offset),
+            T::quantize(out.get_alpha(), offset),
+        )
+    }
+}
+
+impl RgbaPixel<f32> {
+    /// Opaque preview of this straight result for `view`; `colour` is the `Solid` backdrop.
+    pub fn view(&self, view: View, colour: Background, x: usize, y: usize) -> RgbaPixel<f32> {
+        let a = self.alpha;
+        let over = |bg: Background| {
+            RgbaPixel::new(
+                self.red * a + bg.red * (1.0 - a),
+                self.green * a + bg.green * (1.0 - a),
+                self.blue * a + bg.blue * (1.0 - a),
+                1.0,
+            )
+        };
+        match view {
+            View::Final => self.clone(),
+            View::Alpha => RgbaPixel::new(a, a, a, 1.0),
+            View::Checkerboard => {
+                let c = View::checker(x, y);
+                over(Background::new(c, c, c))
+            }
+            View::Solid => over(colour),
+            View::Diagnostic => {
+                const EPSILON: f32 = 1e-6;
+                let clipped = [self.red, self.green, self.blue].iter().any(|c| !(-EPSILON..=1.0 + EPSILON).contains(c));
+                if clipped {
+                    RgbaPixel::new(1.0, 0.0, 0.0, 1.0)
+                } else if a >= 1.0 {
+                    RgbaPixel::new(0.0, 1.0, 0.0, 1.0)
+                } else if a <= 0.0 {
+                    RgbaPixel::new(0.0, 0.0, 1.0, 1.0)
+                } else {
+                    let dim = over(Background::BLACK);
+                    RgbaPixel::new(dim.red * 0.5, dim.green * 0.5, dim.blue * 0.5, 1.0)
+                }
+            }
+        }
+    }
+}
+
+/// Luma/chroma weights of a YUV encoding.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum YuvMatrix {
+    #[default]
+    Rec601,
+    Rec709,
+}
+
+impl YuvMatrix {
+    /// `(Kr, Kb)`; `Kg` is what remains.
+    #[inline]
+    pub const fn coefficients(self) -> (f32, f32) {
+        match self {
+            YuvMatrix::Rec601 => (0.299, 0.114),
+            YuvMatrix::Rec709 => (0.2126, 0.0722),
+        }
+    }
+}
+
+/// How YUV channel values map to normalised luma and signed chroma.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum YuvRange {
+    /// Studio levels: luma 16..235 and chroma 16..240 around 128, in 8-bit terms.
+    /// Premiere's `VUYA_4444_8u` formats.
+    #[default]
+    Video,
+    /// Luma `0..1` and chroma `-0.5..0.5` around zero. Premiere's `VUYA_4444_32f` formats.
+    Full,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct YuvFormat {
+    pub matrix: YuvMatrix,
+    pub range: YuvRange,
+}
+
+impl YuvFormat {
+    pub const fn new(matrix: YuvMatrix, range: YuvRange) -> Self {
+        Self { matrix, range }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct YuvaPixel<T: PixelCompute> {
+    y: T,
+    u: T,
+    v: T,
+    alpha: T,
+}
+
+impl<T> YuvaPixel<T> where T: PixelCompute {
+    pub fn new(y: T, u: T, v: T, a: T) -> Self {
+        Self { y, u, v, alpha: a }
+    }
+
+    #[inline]
+    pub fn get_y(&self) -> T { self.y }
+    #[inline]
+    pub fn get_u(&self) -> T { self.u }
+    #[inline]
+    pub fn get_v(&self) -> T { self.v }
+    #[inline]
+    pub fn get_alpha(&self) -> T { self.alpha }
+
+    pub fn zero() -> Self {
+        Self { y: T::ZERO, u: T::ZERO, v: T::ZERO, alpha: T::ZERO }
+    }
+
+    /// Encodes `rgba` with `format`, truncating integer channels like `from_f32`.
+    pub fn from_rgba(rgba: &RgbaPixel<T>, format: YuvFormat) -> Self {
+        Self::from_rgb_f32(rgba.red.to_f32(), rgba.green.to_f32(), rgba.blue.to_f32(), rgba.alpha, format, 0.0)
+    }
+
+    /// Decodes into RGB at the same depth. Out-of-gamut integer values saturate.
+    pub fn to_rgba(&self, format: YuvFormat) -> RgbaPixel<T> {
+        let (r, g, b) = self.to_rgb_f32(format);
+        RgbaPixel::new(T::from_f32(r), T::from_f32(g), T::from_f32(b), self.alpha)
+    }
+
+    /// Applies `settings` to the pixel at `x`, `y` by way of RGB: the colour is decoded,
+    /// processed in `f32` and encoded again, quantising with `settings.quantize`.
+    pub fn apply_at(&self, settings: &Settings, format: YuvFormat, x: usize, y: usize) -> YuvaPixel<T> {
+        let (r, g, b) = self.to_rgb_f32(format);
+        let rgb_settings = Settings { quantize: Quantize::Truncate, ..*settings };
+        let out = RgbaPixel::<f32>::new(r, g, b, self.alpha.to_f32()).apply_at(&rgb_settings, x, y);
+        let offset = settings.quantize.offset(x, y);
+        Self::from_rgb_f32(out.red, out.green, out.blue, T::quantize(out.alpha, offset), format, offset)
+    }
+
+    fn to_rgb_f32(&self, format: YuvFormat) -> (f32, f32, f32) {
+        let (kr, kb) = format.matrix.coefficients();
+        let kg = 1.0 - kr - kb;
+        let (y, u, v) = match format.range {
+            YuvRange::Video => (
+                (self.y.to_f32() - 16.0 / 255.0) * (255.0 / 219.0),
+                (self.u.to_f32() - 128.0 / 255.0) * (255.0 / 224.0),
+                (self.v.to_f32() - 128.0 / 255.0) * (255.0 / 224.0),
+            ),
+            YuvRange::Full => (self.y.to_f32(), self.u.to_f32(), self.v.to_f32()),
+        };
+        let r = y + 2.0 * (1.0 - kr) * v;
+        let b = y + 2.0 * (1.0 - kb) * u;
+        let g = (y - kr * r - kb * b) / kg;
+        (r, g, b)
+    }
+
+    fn from_rgb_f32(r: f32, g: f32, b: f32, alpha: T, format: YuvFormat, offset: f32) -> Self {
+        let (kr, kb) = format.matrix.coefficients();
+        let kg = 1.0 - kr - kb;
+        let y = kr * r + kg * g + kb * b;
+        let u = (b - y) / (2.0 * (1.0 - kb));
+        let v = (r - y) / (2.0 * (1.0 - kr));
+        let (y, u, v) = match format.range {
+            YuvRange::Video => (
+                y * (219.0 / 255.0) + 16.0 / 255.0,
+                u * (224.0 / 255.0) + 128.0 / 255.0,
+                v * (224.0 / 255.0) + 128.0 / 255.0,
+            ),
+            YuvRange::Full => (y, u, v),
+        };
+        Self::new(T::quantize(y, offset), T::quantize(u, offset), T::quantize(v, offset), alpha)
+    }
+}
+
+pub(crate) fn max3<T: PartialOrd>(a: T, b: T, c: T) -> T {
+    if a >= b && a >= c { a } else if b >= c { b } else { c }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::{AlphaSource, Background, Hdr, Levels};
+
+    #[test]
+    fn test_rgba_pixel() {
+        let p = RgbaPixel::<u8>::new(1, 2, 3, 4);
+        assert_eq!(p.get_red(), 1);
+        assert_eq!(p.get_green(), 2);
+        assert_eq!(p.get_blue(), 3);
+        assert_eq!(p.get_alpha(), 4);
+    }
+
+    #[test]
+    fn test_yuva_pixel() {
+        let p = YuvaPixel::<u8>::new(1, 2, 3, 4);
+        assert_eq!(p.get_y(), 1);
+        assert_eq!(p.get_u(), 2);
+        assert_eq!(p.get_v(), 3);
+        assert_eq!(p.get_alpha(), 4);
+    }
+
+    #[test]
+    fn test_pixel_compute() {
+        assert_eq!(u8::ZERO, 0);
+        assert_eq!(u8::SCALE, 255.0);
+        assert_eq!(u8::from_f32(0.5), 127);
+        assert_eq!(u8::from_f32(0.0), 0);
+        assert_eq!(u8::from_f32(1.0), 255);
+        assert_eq!(u8::from_f32(0.1), 25);
+        assert_eq!(u8::from_f32(0.9), 229);
+        assert_eq!(u8::from_f32(0.99), 252);
+        assert_eq!(u8::from_f32(0.01), 2);
+        assert_eq!(u8::from_f32(0.001), 0);
+        assert_eq!(u8::from_f32(0.999), 254);
+
+        assert_eq!(u16::ZERO, 0);
+        assert_eq!(u16::SCALE, 65535.0);
+        assert_eq!(u16::from_f32(0.5), 32767);
+        assert_eq!(u16::from_f32(0.0), 0);
+        assert_eq!(u16::from_f32(1.0), 65535);
+        assert_eq!(u16::from_f32(0.1), 6553);
+        assert_eq!(u16::from_f32(0.9), 58981);
+        assert_eq!(u16::from_f32(0.99), 64879);
+        assert_eq!(u16::from_f32(0.01), 655);
+        assert_eq!(u16::from_f32(0.001), 65);
+        assert_eq!(u16::from_f32(0.999), 65469);
+
+        assert_eq!(f32::ZERO, 0.0);
+        assert_eq!(f32::SCALE, 1.0);
+        assert_eq!(f32::from_f32(0.5), 0.5);
+        assert_eq!(f32::from_f32(0.0), 0.0);
+        assert_eq!(f32::from_f32(1.0), 1.0);
+        assert_eq!(f32::from_f32(0.1), 0.1);   
+    }
+
+    #[test]
+    fn test_rgba_pixel_zero() {
+        let p = RgbaPixel::<u8>::zero();
+        assert_eq!(p.get_red(), 0);
+        assert_eq!(p.get_green(), 0);
+        assert_eq!(p.get_blue(), 0);
+        assert_eq!(p.get_alpha(), 0);
+    }
+
+    #[test]
+    fn test_yuva_pixel_zero() {
+        let p = YuvaPixel::<u8>::zero();
+        assert_eq!(p.get_y(), 0);
+        assert_eq!(p.get_u(), 0);
+        assert_eq!(p.get_v(), 0);
+        assert_eq!(p.get_alpha(), 0);
+    }
+
+    #[test]
+    fn test_rgba_pixel_from_f32() {
+        let p = RgbaPixel::<u8>::new(1, 2, 3, 4);
+        let p_f32 = RgbaPixel::<f32>::new(p.red.to_f32(), p.green.to_f32(), p.blue.to_f32(), p.alpha.to_f32());
+        assert_eq!(p_f32.red, 1.0 / 255.0);
+        assert_eq!(p_f32.green, 2.0 / 255.0);
+        assert_eq!(p_f32.blue, 3.0 / 255.0);
+        assert_eq!(p_f32.alpha, 4.0 / 255.0);
+    }
+
+    #[test]
+    fn test_yuva_pixel_from_f32() {
+        let p = YuvaPixel::<u8>::new(1, 2, 3, 4);
+        let p_f32 = YuvaPixel::<f32>::new(p.y.to_f32(), p.u.to_f32(), p.v.to_f32(), p.alpha.to_f32());
+        assert_eq!(p_f32.y, 1.0 / 255.0);
+        assert_eq!(p_f32.u, 2.0 / 255.0);
+        assert_eq!(p_f32.v, 3.0 / 255.0);
+        assert_eq!(p_f32.alpha, 4.0 / 255.0);
+    }
+
+    #[test]
+    fn test_rgba_pixel_to_f32() {
+        let p = RgbaPixel::<u8>::new(1, 2, 3, 4);
+        assert_eq!(p.red.to_f32(), 1.0 / 255.0);
+        assert_eq!(p.green.to_f32(), 2.0 / 255.0);
+        assert_eq!(p.blue.to_f32(), 3.0 / 255.0);
+        assert_eq!(p.alpha.to_f32(), 4.0 / 255.0);
+    }
+
+    #[test]
+    fn test_yuva_pixel_to_f32() {
+        let p = YuvaPixel::<u8>::new(1, 2, 3, 4);
+        assert_eq!(p.y.to_f32(), 1.0 / 255.0);
+        assert_eq!(p.u.to_f32(), 2.0 / 255.0);
+        assert_eq!(p.v.to_f32(), 3.0 / 255.0);
+        assert_eq!(p.alpha.to_f32(), 4.0 / 255.0);
+    }
+
+    #[test]
+    fn test_rgba_to_yuva() {
+        let rgba = RgbaPixel::<f32>::new(0.0, 0.0, 1.0, 1.0);
+        let yuva = YuvaPixel::<f32>::from_rgba(&rgba, FULL_601);
+        let converted_rgba = yuva.to_rgba(FULL_601);
+        assert!((rgba.red - converted_rgba.red).abs() < 1e-3);
+        assert!((rgba.green - converted_rgba.green).abs() < 1e-3);
+        assert!((rgba.blue - converted_rgba.blue).abs() < 1e-3);
+        assert!((rgba.alpha - converted_rgba.alpha).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_yuva_to_rgba() {
+        let rgba = RgbaPixel::<f32>::new(0.392_156_87, 0.588_235_3, 0.784_313_74, 1.0);
+        let yuva = YuvaPixel::<f32>::from_rgba(&rgba, FULL_601);
+        let converted_rgba = yuva.to_rgba(FULL_601);
+        let converted_yuva = YuvaPixel::<f32>::from_rgba(&converted_rgba, FULL_601);
+        assert!((yuva.y - converted_yuva.y).abs() < 1e-3);
+        assert!((yuva.u - converted_yuva.u).abs() < 1e-3);
+        assert!((yuva.v - converted_yuva.v).abs() < 1e-3);
+        assert!((yuva.alpha - converted_yuva.alpha).abs() < 1e-3);
+    }
+
+    const FULL_601: YuvFormat = YuvFormat::new(YuvMatrix::Rec601, YuvRange::Full);
+
+    #[test]
+    fn test_yuv_reference_values() {
+        // White, black and pure red in studio-range 8-bit codes.
+        for matrix in [YuvMatrix::Rec601, YuvMatrix::Rec709] {
+            let format = YuvFormat::new(matrix, YuvRange::Video);
+            let white = YuvaPixel::from_rgba(&RgbaPixel::<u8>::new(255, 255, 255, 255), format);
+            assert!(white.get_y().abs_diff(235) <= 1 && white.get_u().abs_diff(128) <= 1 && white.get_v().abs_diff(128) <= 1, "{white:?}");
+            let black = YuvaPixel::from_rgba(&RgbaPixel::<u8>::new(0, 0, 0, 255), format);
+            assert!(black.get_y().abs_diff(16) <= 1 && black.get_u().abs_diff(128) <= 1 && black.get_v().abs_diff(128) <= 1, "{black:?}");
+            let red = YuvaPixel::from_rgba(&RgbaPixel::<u8>::new(255, 0, 0, 255), format);
+            assert!(red.get_v().abs_diff(240) <= 1, "{red:?}");
+        }
+        let red = YuvaPixel::from_rgba(&RgbaPixel::<u8>::new(255, 0, 0, 255), YuvFormat::new(YuvMatrix::Rec601, YuvRange::Video));
+        assert!(red.get_y().abs_diff(81) <= 1, "{red:?}");
+        let red = YuvaPixel::from_rgba(&RgbaPixel::<u8>::new(255, 0, 0, 255), YuvFormat::new(YuvMatrix::Rec709, YuvRange::Video));
+        assert!(red.get_y().abs_diff(63) <= 1, "{red:?}");
+
+        let red = YuvaPixel::from_rgba(&RgbaPixel::<f32>::new(1.0, 0.0, 0.0, 1.0), FULL_601);
+        assert!((red.get_y() - 0.299).abs() < 1e-6 && (red.get_v() - 0.5).abs() < 1e-6, "{red:?}");
+    }
+
+    #[test]
+    fn test_yuv_round_trip() {
+        for matrix in [YuvMatrix::Rec601, YuvMatrix::Rec709] {
+            let format = YuvFormat::new(matrix, YuvRange::Full);
+            for i in 0..1000u32 {
+                let c = |k: u32| ((i * k) % 1001) as f32 / 1000.0;
+                let rgba = RgbaPixel::<f32>::new(c(7), c(13), c(31), 1.0);
+                let back = YuvaPixel::from_rgba(&rgba, format).to_rgba(format);
+                for (x, y) in [(rgba.red, back.red), (rgba.green, back.green), (rgba.blue, back.blue)] {
+                    assert!((x - y).abs() < 1e-5, "{rgba:?} -> {back:?}");
+                }
+            }
+
+            // 8-bit studio range is lossy; stay within a couple of codes per channel.
+            let format = YuvFormat::new(matrix, YuvRange::Video);
+            for i in 0..=0xFFFFu32 {
+                let yuva = YuvaPixel::<u8>::new(16 + (i >> 8) as u8 % 220, 16 + i as u8 % 225, 16 + (i * 7) as u8 % 225, 255);
+                let rgb = yuva.to_rgba(format);
+                let (r, g, b) = yuva.to_rgb_f32(format);
+                if [r, g, b].iter().any(|c| !(0.0..=1.0).contains(c)) {
+                    continue;
+                }
+                let back = YuvaPixel::from_rgba(&rgb, format);
+                for (x, y) in [(yuva.y, back.y), (yuva.u, back.u), (yuva.v, back.v)] {
+                    assert!(x.abs_diff(y) <= 3, "{yuva:?} -> {rgb:?} -> {back:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_yuv_unmult_matches_rgb() {
+        let settings = Settings::default();
+        for matrix in [YuvMatrix::Rec601, YuvMatrix::Rec709] {
+            let format = YuvFormat::new(matrix, YuvRange::Full);
+            for &(r, g, b) in &[(0.5f32, 0.25, 0.0), (0.1, 0.2, 0.4), (0.0, 0.0, 0.0), (1.0, 1.0, 1.0), (0.3, 0.3, 0.3)] {
+                let rgba = RgbaPixel::new(r, g, b, 1.0);
+                let expected = rgba.unmult_rgba();
+                let out = YuvaPixel::from_rgba(&rgba, format).apply_at(&settings, format, 0, 0).to_rgba(format);
+                for (x, y) in [(expected.red, out.red), (expected.green, out.green), (expected.blue, out.blue), (expected.alpha, out.alpha)] {
+                    assert!((x - y).abs() < 1e-5, "{expected:?} vs {out:?}");
+                }
+            }
+
+            // 8-bit: a half-grey becomes opaque-ish white at half alpha.
+            let format = YuvFormat::new(matrix, YuvRange::Video);
+            let grey = YuvaPixel::from_rgba(&RgbaPixel::<u8>::new(128, 128, 128, 255), format);
+            let out = grey.apply_at(&settings.with_quantize(Quantize::Round), format, 0, 0);
+            assert!(out.get_y().abs_diff(235) <= 1 && out.get_u().abs_diff(128) <= 1 && out.get_v().abs_diff(128) <= 1, "{out:?}");
+            assert!(out.get_alpha().abs_diff(128) <= 1, "{out:?}");
+        }
+    }
+
+    fn unmult_rgba8(r: u8, g: u8, b: u8, a: u8) -> Option<(u8, u8, u8, u8)> {
+        if a == 0 {
+            Some((0, 0, 0, 0))
+        } else {
+            let a = a as f32;
+            let mut r = r as f32;
+            let mut g = g as f32;
+            let mut b = b as f32;
+            if a < 255.0 {
+                r = (r * a) / 255.0;
+                g = (g * a) / 255.0;
+                b = (b * a) / 255.0;
+            }
+    
+            let max_val = max3(r, g, b);
+            if max_val > 0.0 {
+                let scale = 255.0 / max_val;
+                r *= scale;
+                g *= scale;
+                b *= scale;
+            } else {
+                return Some((0, 0, 0, 0));
+            }
+            Some((r as u8, g as u8, b as u8, max_val as u8))
+        }
+    }
+    
+    #[test]
+    fn test_unmult_rgba8() {
+        let (r, g, b, a) = unmult_rgba8(0, 0, 0, 255).unwrap();
+        assert_eq!((r, g, b, a), (0, 0, 0, 0));
+
+        let (r, g, b, a) = unmult_rgba8(255, 255, 255, 255).unwrap();
+        assert_eq!((r, g, b, a), (255, 255, 255, 255));
+
+        let (r, g, b, a) = unmult_rgba8(255, 255, 255, 128).unwrap();
+        assert_eq!((r, g, b, a), (255, 255, 255, 128));
+
+        let (r, g, b, a) = unmult_rgba8(255, 255, 255, 0).unwrap();
+        assert_eq!((r, g, b, a), (0, 0, 0, 0));
+
+        let (r, g, b, a) = unmult_rgba8(255, 127, 127, 255).unwrap();
+        assert_eq!((r, g, b, a), (255, 127, 127, 255));
+    }
+
+    #[test]
+    fn test_pixel_remult() {
+        assert_eq!(RgbaPixel::<u8>::new(255, 128, 0, 255).remult_rgba(), RgbaPixel::new(255, 128, 0, 255));
+        assert_eq!(RgbaPixel::<u8>::new(255, 255, 255, 0).remult_rgba(), RgbaPixel::new(0, 0, 0, 255));
+        assert_eq!(RgbaPixel::<u16>::new(65535, 0, 65535, 65535).remult_rgba(), RgbaPixel::new(65535, 0, 65535, 65535));
+        assert_eq!(RgbaPixel::<f32>::new(1.0, 0.5, 0.25, 0.5).remult_rgba(), RgbaPixel::new(0.5, 0.25, 0.125, 1.0));
+    }
+
+    fn assert_round_trip<T: PixelCompute>(values: &[T], tolerance: f32) {
+        let close = |a: T, b: T| (a.to_f32() - b.to_f32()).abs() <= tolerance;
+        for &r in values {
+            for &g in values {
+                for &b in values {
+                    let opaque = RgbaPixel::new(r, g, b, T::from_f32(1.0));
+                    let back = opaque.unmult_rgba().remult_rgba();
+                    assert!(close(back.red, r) && close(back.green, g) && close(back.blue, b), "{opaque:?} -> {back:?}");
+                    assert_eq!(back.alpha, T::from_f32(1.0));
+
+                    let straight = opaque.unmult_rgba();
+                    let again = straight.remult_rgba().unmult_rgba();
+                    assert!(close(again.alpha, straight.alpha), "{straight:?} -> {again:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_remult_round_trip() {
+        assert_round_trip::<u8>(&[0, 1, 17, 64, 128, 200, 254, 255], 2.0 / 255.0);
+        assert_round_trip::<u16>(&[0, 1, 300, 16384, 32768, 50000, 65535], 2.0 / 65535.0);
+        assert_round_trip::<f32>(&[0.0, 0.001, 0.1, 0.25, 0.5, 0.75, 1.0], 1e-6);
+    }
+
+    #[test]
+    fn test_unmult_levels() {
+        let settings = Settings::default().with_levels(Levels::new(0.1, 0.5));
+
+        // Near-black noise disappears, bright pixels become opaque at every depth.
+        assert_eq!(u8::unmult(&RgbaPixel::new(20, 10, 5, 255), &settings), RgbaPixel::zero());
+        assert_eq!(RgbaPixel::<u16>::new(5000, 1000, 0, 65535).unmult_rgba_with(&settings), RgbaPixel::zero());
+        assert_eq!(RgbaPixel::<f32>::new(0.08, 0.02, 0.0, 1.0).unmult_rgba_with(&settings), RgbaPixel::zero());
+
+        assert_eq!(u8::unmult(&RgbaPixel::new(200, 100, 0, 255), &settings).get_alpha(), 254);
+        assert_eq!(RgbaPixel::<u16>::new(40000, 20000, 0, 65535).unmult_rgba_with(&settings).get_alpha(), 65535);
+        let p = RgbaPixel::<f32>::new(0.8, 0.4, 0.0, 1.0).unmult_rgba_with(&settings);
+        assert_eq!(p, RgbaPixel::new(0.8, 0.4, 0.0, 1.0));
+
+        // In between, colour is scaled so it still composites back to the input.
+        let p = RgbaPixel::<f32>::new(0.2, 0.1, 0.0, 1.0).unmult_rgba_with(&settings);
+        assert!((p.get_alpha() - 0.25).abs() < 1e-6);
+        assert!((p.get_red() * p.get_alpha() - 0.2).abs() < 1e-6);
+        assert!((p.get_green() * p.get_alpha() - 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_unmult_alpha_source() {
+        // Saturated red: max makes it opaque, luminance keeps it mostly transparent.
+        let settings = Settings::default().with_alpha_source(AlphaSource::Rec709);
+        let p = RgbaPixel::<f32>::new(1.0, 0.0, 0.0, 1.0).unmult_rgba_with(&settings);
+        assert!((p.get_alpha() - 0.2126).abs() < 1e-6);
+        assert!((p.get_red() * p.get_alpha() - 1.0).abs() < 1e-6);
+
+        let p = u8::unmult(&RgbaPixel::new(255, 0, 0, 255), &settings);
+        assert_eq!(p.get_alpha(), 53);
+        assert_eq!((p.get_red(), p.get_green(), p.get_blue()), (255, 0, 0));
+
+        let p = RgbaPixel::<u16>::new(65535, 0, 0, 65535).unmult_rgba_with(&settings);
+        assert_eq!(p.get_alpha(), 13932);
+        assert_eq!(p.get_red(), 65535);
+
+        // Single channels and min pick exactly that value.
+        let settings = Settings::default().with_alpha_source(AlphaSource::Green);
+        assert_eq!(RgbaPixel::<f32>::new(0.9, 0.5, 0.1, 1.0).unmult_rgba_with(&settings).get_alpha(), 0.5);
+        let settings = Settings::default().with_alpha_source(AlphaSource::Min);
+        assert_eq!(RgbaPixel::<f32>::new(0.9, 0.5, 0.1, 1.0).unmult_rgba_with(&settings).get_alpha(), 0.1);
+        assert_eq!(RgbaPixel::<f32>::new(0.9, 0.5, 0.0, 1.0).unmult_rgba_with(&settings), RgbaPixel::zero());
+    }
+
+    #[test]
+    fn test_unmult_background_black_unchanged() {
+        let settings = Settings::default().with_background(Background::BLACK);
+        for &(r, g, b, a) in &[(0.2, 0.4, 0.8, 1.0), (0.0, 0.0, 0.0, 1.0), (1.0, 0.5, 0.0, 0.5), (0.3, 0.3, 0.3, 0.0)] {
+            let p = RgbaPixel::<f32>::new(r, g, b, a);
+            assert_eq!(p.unmult_rgba_with(&settings), p.unmult_rgba());
+        }
+        for v in 0..=255u8 {
+            let p = RgbaPixel::<u8>::new(v, v / 2, 255 - v, 255);
+            assert_eq!(p.apply(&settings), p.apply(&Settings::default()));
+        }
+    }
+
+    #[test]
+    fn test_unmult_background_round_trip() {
+        let bg = Background::new(0.1, 0.15, 0.3);
+        let settings = Settings::default().with_background(bg);
+
+        // Composite known foregrounds over the background, then pull them back out.
+        for &(r, g, b, a) in &[(1.0, 0.8, 0.2, 0.5), (0.0, 0.0, 0.0, 0.25), (0.9, 0.9, 0.9, 1.0), (0.5, 0.6, 0.7, 0.75)] {
+            let composite = RgbaPixel::<f32>::new(r, g, b, a).remult_rgba_over(bg);
+            let straight = composite.unmult_rgba_with(&settings);
+            let rebuilt = straight.remult_rgba_over(bg);
+            assert!(straight.get_alpha() <= a + 1e-6, "{straight:?}");
+            for (x, y) in [(rebuilt.get_red(), composite.get_red()), (rebuilt.get_green(), composite.get_green()), (rebuilt.get_blue(), composite.get_blue())] {
+                assert!((x - y).abs() < 1e-5, "{rebuilt:?} vs {composite:?}");
+            }
+        }
+
+        // The background itself becomes fully transparent.
+        assert_eq!(RgbaPixel::<f32>::new(0.1, 0.15, 0.3, 1.0).unmult_rgba_with(&settings), RgbaPixel::zero());
+
+        // Negative, out-of-gamut channels against a zero background channel stay finite.
+        let red = Settings::default().with_background(Background::new(0.5, 0.0, 0.0));
+        let p = RgbaPixel::<f32>::new(0.75, -0.1, 0.0, 1.0).unmult_rgba_with(&red);
+        assert!([p.get_red(), p.get_green(), p.get_blue(), p.get_alpha()].iter().all(|c| c.is_finite()), "{p:?}");
+        assert!((p.get_alpha() - 0.5).abs() < 1e-6, "{p:?}");
+
+        // Integer depths go through the same float path.
+        let p = RgbaPixel::<u8>::new(255, 38, 77, 255).apply(&settings);
+        assert_eq!((p.get_red(), p.get_alpha()), (255, 255));
+        assert!(RgbaPixel::<u16>::new(6554, 9830, 19661, 65535).apply(&settings).get_alpha() <= 1);
+    }
+
+    #[test]
+    fn test_unscreen_multiply_round_trip() {
+        for &(r, g, b, a) in &[(0.2, 0.4, 0.8, 1.0), (0.0, 0.0, 0.0, 0.5), (0.9, 0.1, 0.5, 0.25), (1.0, 1.0, 0.0, 0.75)] {
+            let composite = RgbaPixel::<f32>::new(r, g, b, a).multiply_over_white();
+            let straight = composite.unscreen_rgba();
+            let rebuilt = straight.multiply_over_white();
+            for (x, y) in [(rebuilt.get_red(), composite.get_red()), (rebuilt.get_green(), composite.get_green()), (rebuilt.get_blue(), composite.get_blue())] {
+                assert!((x - y).abs() < 1e-6, "{rebuilt:?} vs {composite:?}");
+            }
+        }
+        // White is fully transparent, black fully opaque black.
+        assert_eq!(RgbaPixel::<f32>::new(1.0, 1.0, 1.0, 1.0).unscreen_rgba(), RgbaPixel::zero());
+        assert_eq!(RgbaPixel::<f32>::new(0.0, 0.0, 0.0, 1.0).unscreen_rgba(), RgbaPixel::new(0.0, 0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_unscreen_lut_matches_float() {
+        let settings = Settings::new(Mode::Unscreen);
+        for i in 0..=0xFFFFu32 {
+            let p = RgbaPixel::<u8>::new((i >> 8) as u8, i as u8, (i * 7) as u8, 255);
+            let lut = p.apply(&settings);
+            let float = p.unscreen_rgba();
+            assert!(lut.get_alpha().abs_diff(float.get_alpha()) <= 1, "{p:?}: {lut:?} vs {float:?}");
+            if lut.get_alpha() > 0 {
+                for (x, y) in [(lut.get_red(), float.get_red()), (lut.get_green(), float.get_green()), (lut.get_blue(), float.get_blue())] {
+                    assert!(x.abs_diff(y) <= 2, "{p:?}: {lut:?} vs {float:?}");
+                }
+            }
+        }
+
+        let p = RgbaPixel::<u16>::new(0, 32768, 65535, 65535).apply(&settings);
+        assert_eq!((p.get_red(), p.get_blue(), p.get_alpha()), (0, 65535, 65535));
+    }
+
+    #[test]
+    fn test_unmult_linear_light() {
+        for transfer in [Transfer::Srgb, Transfer::Rec709, Transfer::Gamma22, Transfer::Gamma24] {
+            let settings = Settings::default().with_transfer(transfer);
+
+            // A half-strength linear glow of pure orange.
+            let (r, g) = (transfer.encode(0.5), transfer.encode(0.25));
+            let p = RgbaPixel::<f32>::new(r, g, 0.0, 1.0).apply(&settings);
+            assert!((p.get_alpha() - 0.5).abs() < 1e-5, "{transfer:?}: {p:?}");
+            assert!((p.get_red() - 1.0).abs() < 1e-5, "{transfer:?}: {p:?}");
+            assert!((p.get_green() - transfer.encode(0.5)).abs() < 1e-5, "{transfer:?}: {p:?}");
+
+            // Compositing the straight result over black in linear light rebuilds the input.
+            let rebuilt = transfer.decode(p.get_green()) * p.get_alpha();
+            assert!((transfer.encode(rebuilt) - g).abs() < 1e-5, "{transfer:?}");
+
+            let p8 = RgbaPixel::<u8>::new(188, 137, 0, 255).apply(&settings);
+            let p16 = RgbaPixel::<u16>::new(48316, 35209, 0, 65535).apply(&settings);
+            let pf = RgbaPixel::<f32>::new(188.0 / 255.0, 137.0 / 255.0, 0.0, 1.0).apply(&settings);
+            assert!((p8.get_alpha() as f32 / 255.0 - pf.get_alpha()).abs() <= 1.0 / 255.0, "{transfer:?}");
+            assert!((p16.get_alpha() as f32 / 65535.0 - pf.get_alpha()).abs() <= 2.0 / 65535.0 + 1e-4, "{transfer:?}");
+            assert!((p8.get_green() as f32 / 255.0 - pf.get_green()).abs() <= 1.0 / 255.0, "{transfer:?}");
+        }
+
+        // Linear is today's behaviour.
+        let p = RgbaPixel::<u8>::new(200, 100, 50, 255);
+        assert_eq!(p.apply(&Settings::default().with_transfer(Transfer::Linear)), p.apply(&Settings::default()));
+    }
+
+    #[test]
+    fn test_unmult_hdr_superwhite() {
+        let superwhite = RgbaPixel::<f32>::new(4.0, 2.0, 1.0, 1.0);
+        let composite = |p: &RgbaPixel<f32>| [p.get_red() * p.get_alpha(), p.get_green() * p.get_alpha(), p.get_blue() * p.get_alpha()];
+
+        // Today's behaviour: alpha follows the matte past 1.
+        let p = superwhite.unmult_rgba_with(&Settings::default().with_hdr(Hdr::Passthrough));
+        assert_eq!(p, RgbaPixel::new(1.0, 0.5, 0.25, 4.0));
+
+        let p = superwhite.unmult_rgba_with(&Settings::default().with_hdr(Hdr::ClampAlpha));
+        assert_eq!(p, RgbaPixel::new(4.0, 2.0, 1.0, 1.0));
+        assert_eq!(composite(&p), [4.0, 2.0, 1.0]);
+
+        let p = superwhite.unmult_rgba_with(&Settings::default().with_hdr(Hdr::SoftKnee));
+        assert!(p.get_alpha() > Hdr::SOFT_KNEE && p.get_alpha() < 1.0, "{p:?}");
+        assert_eq!((p.get_red(), p.get_green(), p.get_blue()), (1.0, 0.5, 0.25));
+
+        let p = superwhite.unmult_rgba_with(&Settings::default().with_hdr(Hdr::PreserveEnergy));
+        assert!(p.get_alpha() > Hdr::SOFT_KNEE && p.get_alpha() < 1.0, "{p:?}");
+        for (c, expected) in composite(&p).into_iter().zip([4.0, 2.0, 1.0]) {
+            assert!((c - expected).abs() < 1e-5, "{p:?}");
+        }
+
+        // The knee also rolls off in-range integer values above it.
+        let p = RgbaPixel::<u8>::new(255, 128, 0, 255).apply(&Settings::default().with_hdr(Hdr::SoftKnee));
+        assert_eq!(p.get_alpha(), u8::from_f32(Hdr::knee(1.0)));
+
+        // In-range values below the knee are untouched by every mode.
+        let p = RgbaPixel::<f32>::new(0.6, 0.3, 0.15, 1.0);
+        for hdr in Hdr::ALL {
+            assert_eq!(p.unmult_rgba_with(&Settings::default().with_hdr(hdr)), p.unmult_rgba(), "{hdr:?}");
+        }
+    }
+
+    #[test]
+    fn test_ae16_range() {
+        assert_eq!(Ae16::SCALE, 32768.0);
+        assert_eq!(Ae16::from_f32(1.0), Ae16(32768));
+        assert_eq!(Ae16::from_f32(0.5), Ae16(16384));
+        assert_eq!(Ae16::from_f32(4.0), Ae16(32768));
+        assert_eq!(Ae16::from_f32(-1.0), Ae16(0));
+        assert_eq!(Ae16(32768).to_f32(), 1.0);
+
+        // White is opaque white, not a half-intensity grey.
+        let p = RgbaPixel::new(Ae16(32768), Ae16(16384), Ae16(0), Ae16(32768)).unmult_rgba();
+        assert_eq!(p, RgbaPixel::new(Ae16(32768), Ae16(16384), Ae16(0), Ae16(32768)));
+        let p = RgbaPixel::new(Ae16(16384), Ae16(8192), Ae16(0), Ae16(32768)).unmult_rgba();
+        assert_eq!(p, RgbaPixel::new(Ae16(32768), Ae16(16384), Ae16(0), Ae16(16384)));
+    }
+
+    #[test]
+    fn test_ae16_matches_u8() {
+        let to_ae16 = |v: u8| Ae16(((v as u32 * 32768 + 127) / 255) as u16);
+        for settings in [Settings::default(), Settings::new(Mode::Remult), Settings::new(Mode::Unscreen)] {
+            for i in (0..=0xFFFFu32).step_by(7) {
+                let (r, g, b, a) = ((i >> 8) as u8, i as u8, (i * 3) as u8, ((i * 5) >> 4) as u8 | 0x80);
+                let p8 = RgbaPixel::new(r, g, b, a).apply(&settings);
+                let p16 = RgbaPixel::new(to_ae16(r), to_ae16(g), to_ae16(b), to_ae16(a)).apply(&settings);
+                if p8.get_alpha() < 4 {
+                    continue;
+                }
+                let pairs = [(p8.get_red(), p16.get_red()), (p8.get_green(), p16.get_green()), (p8.get_blue(), p16.get_blue()), (p8.get_alpha(), p16.get_alpha())];
+                for (c8, c16) in pairs {
+                    // The 8-bit LUT truncates colour against a truncated alpha, so allow a few codes.
+                    assert!((c8.to_f32() - c16.to_f32()).abs() <= 3.0 / 255.0, "{settings:?}: {p8:?} vs {p16:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_quantize_round() {
+        let settings = Settings::default().with_quantize(Quantize::Round);
+
+        // The rounding LUT agrees with the float path, allowing for ties.
+        for i in 0..=0xFFFFu32 {
+            let p = RgbaPixel::<u8>::new((i >> 8) as u8, i as u8, (i * 7) as u8, 255);
+            let lut = p.apply(&settings);
+            let float = p.apply_float(&settings.with_quantize(Quantize::Round), 0, 0);
+            let pairs = [(lut.get_red(), float.get_red()), (lut.get_green(), float.get_green()), (lut.get_blue(), float.get_blue()), (lut.get_alpha(), float.get_alpha())];
+            for (x, y) in pairs {
+                assert!(x.abs_diff(y) <= 1, "{p:?}: {lut:?} vs {float:?}");
+            }
+        }
+
+        // Truncation biases dark; rounding does not.
+        let p = RgbaPixel::<u8>::new(200, 100, 0, 255).apply(&settings);
+        assert_eq!(p, RgbaPixel::new(255, 128, 0, 200));
+        let p = RgbaPixel::<u16>::new(40000, 20001, 0, 65535).apply(&settings);
+        assert_eq!(p, RgbaPixel::new(65535, 32769, 0, 40000));
+        let p = RgbaPixel::new(Ae16(20000), Ae16(10001), Ae16(0), Ae16(32768)).apply(&settings);
+        assert_eq!(p, RgbaPixel::new(Ae16(32768), Ae16(16386), Ae16(0), Ae16(20000)));
+
+        // Truncate is the legacy path.
+        let p = RgbaPixel::<u8>::new(200, 100, 50, 255);
+        assert_eq!(p.apply(&Settings::default().with_quantize(Quantize::Truncate)), p.apply(&Settings::default()));
+
+        // Round and Truncate agree on what a combine mode does with zero input alpha.
+        for combine in AlphaCombine::ALL {
+            let settings = Settings::default().with_alpha_combine(combine);
+            let p = RgbaPixel::<u8>::new(204, 102, 0, 0);
+            let (rounded, truncated) = (p.apply(&settings.with_quantize(Quantize::Round)), p.apply(&settings));
+            assert_eq!(rounded == RgbaPixel::zero(), truncated == RgbaPixel::zero(), "{combine:?}: {rounded:?} vs {truncated:?}");
+        }
+    }
+
+    #[test]
+    fn test_quantize_dither() {
+        let settings = Settings::default().with_quantize(Quantize::Dither);
+
+        // A flat value between two codes averages out to the exact value across the tile.
+        let mut sum = 0.0;
+        for y in 0..8 {
+            for x in 0..8 {
+                let out = RgbaPixel::<u8>::new(77, 38, 0, 255).apply_at(&settings, x, y);
+                assert_eq!(out.get_red(), 255);
+                assert!(matches!(out.get_green(), 125 | 126), "{out:?}");
+                sum += out.get_green() as f32;
+            }
+        }
+        let exact = 38.0 / 77.0 * 255.0;
+        assert!((sum / 64.0 - exact).abs() < 1.0 / 32.0, "{} vs {exact}", sum / 64.0);
+
+        // Float output is never quantised.
+        let p = RgbaPixel::<f32>::new(0.3, 0.15, 0.0, 1.0);
+        assert_eq!(p.apply_at(&settings, 3, 4), p.unmult_rgba());
+    }
+
+    #[test]
+    fn test_half_and_double() {
+        assert_round_trip::<f16>(&[0.0, 0.001, 0.1, 0.25, 0.5, 0.75, 1.0].map(f16::from_f32), 2e-3);
+        assert_round_trip::<f64>(&[0.0, 0.001, 0.1, 0.25, 0.5, 0.75, 1.0], 1e-6);
+
+        for &(r, g, b, a) in &[(0.5f32, 0.25, 0.0, 1.0), (0.1, 0.2, 0.4, 0.5), (2.0, 1.0, 0.5, 1.0), (0.0, 0.0, 0.0, 1.0)] {
+            let expected = RgbaPixel::new(r, g, b, a).unmult_rgba();
+            let half = RgbaPixel::new(f16::from_f32(r), f16::from_f32(g), f16::from_f32(b), f16::from_f32(a)).unmult_rgba();
+            let double = RgbaPixel::new(r as f64, g as f64, b as f64, a as f64).apply(&Settings::default());
+            for (e, h, d) in [
+                (expected.red, half.red, double.red),
+                (expected.green, half.green, double.green),
+                (expected.blue, half.blue, double.blue),
+                (expected.alpha, half.alpha, double.alpha),
+            ] {
+                assert!((e - f16::to_f32(h)).abs() <= e.abs() * 1e-3, "{expected:?} vs {half:?}");
+                assert!((e as f64 - d).abs() <= 1e-6, "{expected:?} vs {double:?}");
+            }
+        }
+
+        // Doubles keep precision f32 cannot represent.
+        let p = RgbaPixel::<f64>::new(0.3, 0.1 + 1e-12, 0.0, 1.0).apply(&Settings::default());
+        assert_eq!(p.alpha, 0.3);
+        assert!((p.green - (0.1 + 1e-12) / 0.3).abs() < 1e-15);
+
+        let p = RgbaPixel::<f16>::new(f16::ONE, f16::from_f32(0.5), f16::ZERO, f16::from_f32(0.5)).remult_rgba();
+        assert_eq!(p, RgbaPixel::new(f16::from_f32(0.5), f16::from_f32(0.25), f16::ZERO, f16::ONE));
+    }
+
+    #[test]
+    fn test_alpha_combine_modes() {
+        // Orange at 50% input alpha.
+        let expected = [
+            (AlphaCombine::Replace, (1.0, 0.5, 0.0, 0.4)),
+            (AlphaCombine::Multiply, (1.0, 0.5, 0.0, 0.4)),
+            (AlphaCombine::Min, (1.0, 0.5, 0.0, 0.5)),
+            (AlphaCombine::Max, (1.0, 0.5, 0.0, 0.8)),
+            (AlphaCombine::Ignore, (1.0, 0.5, 0.0, 0.8)),
+        ];
+        for (combine, (r, g, b, a)) in expected {
+            let settings = Settings::default().with_alpha_combine(combine);
+            let p = RgbaPixel::<f32>::new(0.8, 0.4, 0.0, 0.5).apply(&settings);
+            assert_eq!(p, RgbaPixel::new(r, g, b, a), "{combine:?}");
+
+            // Every depth agrees within its quantisation.
+            let p8 = RgbaPixel::<u8>::new(204, 102, 0, 128).apply(&settings);
+            let p16 = RgbaPixel::<u16>::new(52428, 26214, 0, 32768).apply(&settings);
+            let p64 = RgbaPixel::<f64>::new(0.8, 0.4, 0.0, 0.5).apply(&settings);
+            for (c8, c16, c64, c) in [
+                (p8.red, p16.red, p64.red, r),
+                (p8.green, p16.green, p64.green, g),
+                (p8.blue, p16.blue, p64.blue, b),
+                (p8.alpha, p16.alpha, p64.alpha, a),
+            ] {
+                assert!((c8.to_f32() - c).abs() <= 2.0 / 255.0, "{combine:?}: {p8:?}");
+                assert!((c16.to_f32() - c).abs() <= 2e-4, "{combine:?}: {p16:?}");
+                assert!((c64.to_f32() - c).abs() <= 1e-6, "{combine:?}: {p64:?}");
+            }
+        }
+
+        // Zero input alpha: only Max and Ignore keep a matte.
+        for combine in AlphaCombine::ALL {
+            let settings = Settings::default().with_alpha_combine(combine);
+            let p = RgbaPixel::<f32>::new(0.8, 0.4, 0.0, 0.0).apply(&settings);
+            let p8 = RgbaPixel::<u8>::new(204, 102, 0, 0).apply(&settings);
+            let p64 = RgbaPixel::<f64>::new(0.8, 0.4, 0.0, 0.0).apply(&settings);
+            if combine.zero_input_is_transparent() {
+                assert_eq!(p.alpha, 0.0, "{combine:?}");
+                assert_eq!(p8.alpha, 0, "{combine:?}");
+                assert_eq!(p64.alpha, 0.0, "{combine:?}");
+            } else {
+                assert_eq!(p, RgbaPixel::new(1.0, 0.5, 0.0, 0.8), "{combine:?}");
+                assert_eq!(p8, RgbaPixel::new(255, 128, 0, 204), "{combine:?}");
+                assert!((p64.alpha - 0.8).abs() <= 1e-6, "{combine:?}: {p64:?}");
+            }
+        }
+
+        // An empty matte under Max keeps the input alpha over black.
+        let p = RgbaPixel::<f32>::new(0.0, 0.0, 0.0, 0.7).apply(&Settings::default().with_alpha_combine(AlphaCombine::Max));
+        assert_eq!(p, RgbaPixel::new(0.0, 0.0, 0.0, 0.7));
+    }
+
+    #[test]
+    fn test_premultiplied_output_over_black() {
+        // Premultiplied output is what gets added over black, so it must be the input again.
+        let settings = Settings::default().with_association(Association::Premultiplied);
+        for i in 0..1000u32 {
+            let c = |k: u32| ((i * k) % 1001) as f32 / 1000.0;
+            let p = RgbaPixel::<f32>::new(c(7), c(13), c(31), 1.0);
+            let out = p.apply(&settings);
+            assert_eq!(out.alpha, p.unmult_rgba().alpha);
+            for (x, y) in [(out.red, p.red), (out.green, p.green), (out.blue, p.blue)] {
+                assert!((x - y).abs() < 1e-6, "{p:?} -> {out:?}");
+            }
+        }
+
+        let settings = settings.with_quantize(Quantize::Round);
+        for i in 0..=0xFFFFu32 {
+            let p = RgbaPixel::<u8>::new((i >> 8) as u8, i as u8, (i * 7) as u8, 255);
+            let out = p.apply(&settings);
+            for (x, y) in [(out.red, p.red), (out.green, p.green), (out.blue, p.blue)] {
+                assert!(x.abs_diff(y) <= 1, "{p:?} -> {out:?}");
+            }
+        }
+
+        // Also through a transfer curve and an HDR clamp.
+        let settings = Settings::default()
+            .with_association(Association::Premultiplied)
+            .with_transfer(Transfer::Srgb)
+            .with_hdr(Hdr::ClampAlpha);
+        let p = RgbaPixel::<f32>::new(2.0, 0.5, 0.1, 1.0);
+        let out = p.apply(&settings);
+        assert_eq!(out.alpha, 1.0);
+        for (x, y) in [(out.red, p.red), (out.green, p.green), (out.blue, p.blue)] {
+            assert!((x - y).abs() < 1e-5, "{p:?} -> {out:?}");
+        }
+
+        // Straight output stays the default.
+        assert_eq!(Settings::default().association, Association::Straight);
+    }
+
+    #[test]
+    fn test_views() {
+        let p = RgbaPixel::<f32>::new(0.4, 0.2, 0.0, 1.0);
+        let view = |view: View| p.apply_at(&Settings::default().with_view(view), 3, 20);
+
+        assert_eq!(view(View::Final), p.unmult_rgba());
+        assert_eq!(view(View::Alpha), RgbaPixel::new(0.4, 0.4, 0.4, 1.0));
+
+        // (3, 20) is on a dark square, (20, 20) on a light one.
+        let checker = view(View::Checkerboard);
+        assert!((checker.green - (0.2 + 0.5 * 0.6)).abs() < 1e-6, "{checker:?}");
+        let light = p.apply_at(&Settings::default().with_view(View::Checkerboard), 20, 20);
+        assert!((light.green - (0.2 + 0.75 * 0.6)).abs() < 1e-6, "{light:?}");
+
+        let settings = Settings::default().with_view(View::Solid).with_view_colour(Background::new(0.0, 0.0, 1.0));
+        let solid = p.apply(&settings);
+        assert!((solid.red - 0.4).abs() < 1e-6 && (solid.blue - 0.6).abs() < 1e-6, "{solid:?}");
+
+        // 8-bit output goes through the same float path.
+        let p8 = RgbaPixel::<u8>::new(102, 51, 0, 255).apply(&Settings::default().with_view(View::Alpha));
+        assert_eq!((p8.red, p8.alpha), (102, 255));
+    }
+
+    #[test]
+    fn test_view_diagnostic() {
+        let settings = Settings::default().with_view(View::Diagnostic);
+        let red = RgbaPixel::new(1.0, 0.0, 0.0, 1.0);
+        let green = RgbaPixel::new(0.0, 1.0, 0.0, 1.0);
+        let blue = RgbaPixel::new(0.0, 0.0, 1.0, 1.0);
+
+        assert_eq!(RgbaPixel::<f32>::new(0.0, 0.0, 0.0, 1.0).apply(&settings), blue);
+        assert_eq!(RgbaPixel::<f32>::new(1.0, 0.5, 0.0, 1.0).apply(&settings), green);
+        assert_eq!(RgbaPixel::<f32>::new(0.4, 0.2, 0.0, 1.0).apply(&settings), RgbaPixel::new(0.2, 0.1, 0.0, 1.0));
+
+        // Luminance alpha leaves saturated colour above 1: clipped.
+        let clipped = settings.with_alpha_source(AlphaSource::Rec709);
+        assert_eq!(RgbaPixel::<f32>::new(1.0, 0.0, 0.0, 1.0).apply(&clipped), red);
+
+        // Superwhites are clipped unless an HDR mode keeps alpha at or below 1.
+        assert_eq!(RgbaPixel::<f32>::new(2.0, 1.0, 0.0, 1.0).apply(&settings), green);
+        assert_eq!(RgbaPixel::<f32>::new(2.0, 1.0, 0.0, 1.0).apply(&settings.with_hdr(Hdr::ClampAlpha)), red);
+
+        let p8 = RgbaPixel::<u8>::new(0, 0, 0, 255).apply(&settings);
+        assert_eq!(p8, RgbaPixel::new(0, 0, 255, 255));
+    }
+
+    #[test]
+    fn test_unmult_default_levels_unchanged() {
+        let settings = Settings::default();
+        for v in (0..=255u8).step_by(5) {
+            let p = RgbaPixel::<u8>::new(v, v / 3, 255 - v, v.wrapping_mul(3));
+            let mut expected = Pixel8::default();
+            inner_render(&Pixel8 { red: p.red, green: p.green, blue: p.blue, alpha: p.alpha }, &mut expected);
+            assert_eq!(u8::unmult(&p, &settings), RgbaPixel::new(expected.red, expected.green, expected.blue, expected.alpha));
+
+            let p = RgbaPixel::<f32>::new(v as f32 / 100.0, 0.3, 0.1, 1.0);
+            assert_eq!(p.unmult_rgba_with(&settings), p.unmult_rgba());
+        }
+    }
+
+    #[test]
+    fn test_pixel_unmult_u8() {
+        let p = RgbaPixel::<u8>::new(0, 0, 0, 255).unmult_rgba();
+        assert_eq!(p, RgbaPixel::<u8>::zero());
+
+        let p = RgbaPixel::<u8>::new(255, 255, 255, 255).unmult_rgba();
+        assert_eq!(p, RgbaPixel::<u8>::new(255, 255, 255, 255));
+
+        let p = RgbaPixel::<u8>::new(255, 255, 255, 128).unmult_rgba();
+        assert_eq!(p, RgbaPixel::<u8>::new(255, 255, 255, 128));
+
+        let p = RgbaPixel::<u8>::new(255, 255, 255, 0).unmult_rgba();
+        assert_eq!(p, RgbaPixel::<u8>::zero());
+
+        let p = RgbaPixel::<u8>::new(255, 127, 127, 255).unmult_rgba();
+        assert_eq!(p, RgbaPixel::<u8>::new(255, 127, 127, 255));
+    }
+}
@@ -0,0 +1,357 @@
+//! Regions of interest for hosts that render parts of a layer, such as After Effects' SmartFX.
+//!
+//! Every effect mode is pixel-independent, so the result is never larger than the input and
+//! an output pixel only needs the input pixel under it. Rects are in layer coordinates and
+//! half-open, like `PF_LRect`.
+
+use crate::buffer::{BufferError, ImageView, ImageViewMut};
+use crate::rgba_to_yuv::{PixelCompute, RgbaPixel};
+use crate::settings::{Settings, View};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rect {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+impl Rect {
+    pub const fn new(left: i32, top: i32, right: i32, bottom: i32) -> Self {
+        Self { left, top, right, bottom }
+    }
+
+    /// `width` x `height` at `left`, `top`.
+    pub const fn with_size(left: i32, top: i32, width: usize, height: usize) -> Self {
+        Self::new(left, top, left + width as i32, top + height as i32)
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize {
+        (self.right - self.left).max(0) as usize
+    }
+
+    #[inline]
+    pub fn height(&self) -> usize {
+        (self.bottom - self.top).max(0) as usize
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.width() == 0 || self.height() == 0
+    }
+
+    /// Overlap of both rects, the empty default if they do not overlap.
+    pub fn intersect(&self, other: &Rect) -> Rect {
+        let rect = Rect::new(self.left.max(other.left), self.top.max(other.top), self.right.min(other.right), self.bottom.min(other.bottom));
+        if rect.is_empty() { Rect::default() } else { rect }
+    }
+
+    /// Smallest rect holding both. Empty rects are ignored.
+    pub fn union(&self, other: &Rect) -> Rect {
+        match (self.is_empty(), other.is_empty()) {
+            (true, true) => Rect::default(),
+            (true, false) => *other,
+            (false, true) => *self,
+            (false, false) => Rect::new(self.left.min(other.left), self.top.min(other.top), self.right.max(other.right), self.bottom.max(other.bottom)),
+        }
+    }
+
+    pub fn translate(&self, dx: i32, dy: i32) -> Rect {
+        Rect::new(self.left + dx, self.top + dy, self.right + dx, self.bottom + dy)
+    }
+}
+
+/// Result rect to report for a `request`, given the input's result rect.
+#[inline]
+pub fn result_rect(request: &Rect, input_result: &Rect) -> Rect {
+    request.intersect(input_result)
+}
+
+/// Input pixels that come out fully transparent, so they can be skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transparent {
+    /// None can be skipped: modes that fill in a backdrop, such as Remult and the
+    /// checkerboard view.
+    None,
+    /// Pixels with every channel zero. The 8-bit Legacy path and combines that ignore the
+    /// input alpha keep colour at zero alpha, so only empty pixels can be skipped.
+    Empty,
+    /// Pixels with zero alpha, whatever their colour.
+    ZeroAlpha,
+}
+
+/// Which input pixels of depth `T` come out fully transparent under `settings`.
+pub fn transparent_input<T: PixelCompute>(settings: &Settings) -> Transparent {
+    let clears = |c: f32| {
+        let v = T::from_f32(c);
+        let out = RgbaPixel::new(v, v, v, T::ZERO).apply(settings);
+        [out.get_red(), out.get_green(), out.get_blue(), out.get_alpha()] == [T::ZERO; 4]
+    };
+    if settings.view != View::Final || !clears(0.0) {
+        Transparent::None
+    } else if [0.5, 1.0].into_iter().all(clears) {
+        Transparent::ZeroAlpha
+    } else {
+        Transparent::Empty
+    }
+}
+
+/// Bounds of the pixels of `src` with non-zero alpha, in view coordinates.
+pub fn opaque_bounds<T: PixelCompute>(src: &ImageView<T>) -> Option<Rect> {
+    let alpha = src.order().offsets()[3];
+    bounds_where(src, |px| px[alpha] != T::ZERO)
+}
+
+/// Bounds of the pixels of `src` with any non-zero channel, in view coordinates.
+pub fn non_empty_bounds<T: PixelCompute>(src: &ImageView<T>) -> Option<Rect> {
+    bounds_where(src, |px| px.iter().any(|&c| c != T::ZERO))
+}
+
+fn bounds_where<T: PixelCompute>(src: &ImageView<T>, keep: impl Fn(&[T]) -> bool) -> Option<Rect> {
+    let mut bounds = Rect::default();
+    for y in 0..src.height() {
+        let row = src.row(y);
+        let Some(first) = row.chunks_exact(4).position(&keep) else {
+            continue;
+        };
+        let last = row.chunks_exact(4).rposition(&keep).unwrap_or(first);
+        bounds = bounds.union(&Rect::new(first as i32, y as i32, last as i32 + 1, y as i32 + 1));
+    }
+    (!bounds.is_empty()).then_some(bounds)
+}
+
+/// Period the dither and checkerboard patterns repeat with.
+const PATTERN_PERIOD: i32 = 64;
+
+/// Renders the part of `dst` that depends on `src` and clears the rest of it.
+///
+/// `dst` covers `output` and `src` covers `input`. Only the overlap is processed, shrunk to
+/// the part of `src` that [`transparent_input`] says can produce output. `process`
+/// receives the cropped views and the layer position of their top left pixel, folded into
+/// the pattern period. Returns the processed rect, empty if nothing was.
+pub fn render_region<T, F, E>(src: &ImageView<T>, input: Rect, dst: &mut ImageViewMut<T>, output: Rect, settings: &Settings, process: F) -> Result<Rect, E>
+where
+    T: PixelCompute,
+    F: FnOnce(&ImageView<T>, &mut ImageViewMut<T>, (usize, usize)) -> Result<(), E>,
+    E: From<BufferError>,
+{
+    if (src.width(), src.height()) != (input.width(), input.height()) || (dst.width(), dst.height()) != (output.width(), output.height()) {
+        return Err(BufferError::SizeMismatch { src: (src.width(), src.height()), dst: (dst.width(), dst.height()) }.into());
+    }
+    let mut area = output.intersect(&input);
+    let skip = if area.is_empty() { Transparent::None } else { transparent_input::<T>(settings) };
+    if skip != Transparent::None {
+        let overlap = crop(src, &input, &area);
+        let bounds = match skip {
+            Transparent::ZeroAlpha => opaque_bounds(&overlap),
+            _ => non_empty_bounds(&overlap),
+        };
+        area = match bounds {
+            Some(bounds) => bounds.translate(area.left, area.top),
+            None => Rect::default(),
+        };
+    }
+    if area != output {
+        dst.fill(T::ZERO);
+    }
+    if area.is_empty() {
+        return Ok(area);
+    }
+
+    let src = crop(src, &input, &area);
+    let (x, y) = ((area.left - output.left) as usize, (area.top - output.top) as usize);
+    let mut dst = dst.crop_mut(x, y, area.width(), area.height()).expect("area lies inside output");
+    let origin = (area.left.rem_euclid(PATTERN_PERIOD) as usize, area.top.rem_euclid(PATTERN_PERIOD) as usize);
+    process(&src, &mut dst, origin)?;
+    Ok(area)
+}
+
+/// `area` of a view covering `bounds`. `area` must lie inside `bounds`.
+fn crop<'a, T: PixelCompute>(view: &ImageView<'a, T>, bounds: &Rect, area: &Rect) -> ImageView<'a, T> {
+    let (x, y) = ((area.left - bounds.left) as usize, (area.top - bounds.top) as usize);
+    view.crop(x, y, area.width(), area.height()).expect("area lies inside bounds")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::{process_buffer, ChannelOrder};
+    use crate::settings::{AlphaCombine, Mode, Quantize};
+
+    const W: usize = 8;
+    const H: usize = 6;
+
+    /// Transparent `W` x `H` image with an opaque 2x2 block at (3, 2).
+    fn sprite() -> Vec<u8> {
+        let mut data = vec![0u8; W * H * 4];
+        for y in 2..4 {
+            for x in 3..5 {
+                data[(y * W + x) * 4..][..4].copy_from_slice(&[200, 100, 50, 128]);
+            }
+        }
+        data
+    }
+
+    fn render(src: &[u8], input: Rect, output: Rect, settings: &Settings) -> (Rect, Vec<u8>) {
+        let mut dst = vec![7u8; output.width() * output.height() * 4];
+        let src_view = ImageView::packed(src, input.width(), input.height(), ChannelOrder::Rgba).unwrap();
+        let mut dst_view = ImageViewMut::packed(&mut dst, output.width(), output.height(), ChannelOrder::Rgba).unwrap();
+        let area = render_region(&src_view, input, &mut dst_view, output, settings, |s, d, _| process_buffer(s, d, settings)).unwrap();
+        (area, dst)
+    }
+
+    fn reference(src: &[u8], settings: &Settings) -> Vec<u8> {
+        let mut dst = vec![0u8; src.len()];
+        process_buffer(
+            &ImageView::packed(src, W, H, ChannelOrder::Rgba).unwrap(),
+            &mut ImageViewMut::packed(&mut dst, W, H, ChannelOrder::Rgba).unwrap(),
+            settings,
+        ).unwrap();
+        dst
+    }
+
+    #[test]
+    fn test_rect_ops() {
+        let a = Rect::new(0, 0, 10, 10);
+        let b = Rect::new(5, -5, 15, 5);
+        assert_eq!(a.intersect(&b), Rect::new(5, 0, 10, 5));
+        assert_eq!(a.union(&b), Rect::new(0, -5, 15, 10));
+        assert_eq!(a.intersect(&Rect::new(20, 20, 30, 30)), Rect::default());
+        assert_eq!(Rect::default().union(&b), b);
+        assert_eq!(Rect::with_size(-2, 3, 4, 5), Rect::new(-2, 3, 2, 8));
+        assert_eq!(Rect::new(3, 0, 1, 4).width(), 0);
+        assert!(Rect::new(3, 0, 1, 4).is_empty());
+        assert_eq!(a.translate(-1, 2), Rect::new(-1, 2, 9, 12));
+    }
+
+    #[test]
+    fn test_result_rect() {
+        let input = Rect::new(0, 0, 1920, 1080);
+        assert_eq!(result_rect(&Rect::new(100, 100, 300, 200), &input), Rect::new(100, 100, 300, 200));
+        assert_eq!(result_rect(&Rect::new(-50, 1000, 50, 1200), &input), Rect::new(0, 1000, 50, 1080));
+        assert!(result_rect(&Rect::new(2000, 0, 2100, 10), &input).is_empty());
+    }
+
+    #[test]
+    fn test_transparent_input() {
+        assert_eq!(transparent_input::<f32>(&Settings::default()), Transparent::ZeroAlpha);
+        assert_eq!(transparent_input::<f32>(&Settings::new(Mode::Unscreen)), Transparent::ZeroAlpha);
+        assert_eq!(transparent_input::<f32>(&Settings::default().with_quantize(Quantize::Dither)), Transparent::ZeroAlpha);
+        assert_eq!(transparent_input::<f32>(&Settings::new(Mode::Remult)), Transparent::None);
+        assert_eq!(transparent_input::<f32>(&Settings::default().with_view(View::Checkerboard)), Transparent::None);
+        assert_eq!(transparent_input::<f32>(&Settings::default().with_alpha_combine(AlphaCombine::Ignore)), Transparent::Empty);
+
+        // The 8-bit Legacy table keeps colour at zero alpha.
+        assert_eq!(transparent_input::<u8>(&Settings::default()), Transparent::Empty);
+        assert_eq!(transparent_input::<u8>(&Settings::default().with_alpha_combine(AlphaCombine::Multiply)), Transparent::ZeroAlpha);
+        assert_eq!(transparent_input::<u8>(&Settings::new(Mode::Remult)), Transparent::None);
+    }
+
+    #[test]
+    fn test_opaque_bounds() {
+        let src = sprite();
+        let view = ImageView::packed(&src, W, H, ChannelOrder::Rgba).unwrap();
+        assert_eq!(opaque_bounds(&view), Some(Rect::new(3, 2, 5, 4)));
+        assert_eq!(opaque_bounds(&view.crop(0, 0, 3, H).unwrap()), None);
+
+        // Colour without alpha is transparent; alpha without colour is not.
+        let mut data = vec![0u8; W * H * 4];
+        data[4..8].copy_from_slice(&[255, 255, 255, 0]);
+        data[(5 * W + 6) * 4 + 3] = 1;
+        let view = ImageView::packed(&data, W, H, ChannelOrder::Rgba).unwrap();
+        assert_eq!(opaque_bounds(&view), Some(Rect::new(6, 5, 7, 6)));
+    }
+
+    #[test]
+    fn test_full_frame_matches_buffer() {
+        let src = sprite();
+        let frame = Rect::with_size(0, 0, W, H);
+        for settings in [Settings::default(), Settings::new(Mode::Remult), Settings::default().with_view(View::Checkerboard)] {
+            let (_, dst) = render(&src, frame, frame, &settings);
+            assert_eq!(dst, reference(&src, &settings), "{settings:?}");
+        }
+    }
+
+    #[test]
+    fn test_skips_transparent_input() {
+        let src = sprite();
+        let frame = Rect::with_size(0, 0, W, H);
+        let (area, dst) = render(&src, frame, frame, &Settings::default());
+        assert_eq!(area, Rect::new(3, 2, 5, 4));
+        assert_eq!(dst, reference(&src, &Settings::default()));
+
+        let empty = vec![0u8; W * H * 4];
+        let (area, dst) = render(&empty, frame, frame, &Settings::default());
+        assert!(area.is_empty());
+        assert!(dst.iter().all(|&v| v == 0));
+
+        let (area, _) = render(&empty, frame, frame, &Settings::new(Mode::Remult));
+        assert_eq!(area, frame);
+
+        // Colour at zero alpha survives the 8-bit Legacy path, so it is rendered, not cleared.
+        let mut src = sprite();
+        src[(W + 1) * 4..][..4].copy_from_slice(&[200, 100, 50, 0]);
+        for settings in [Settings::default(), Settings::default().with_alpha_combine(AlphaCombine::Multiply)] {
+            let (_, dst) = render(&src, frame, frame, &settings);
+            assert_eq!(dst, reference(&src, &settings), "{settings:?}");
+        }
+        assert_ne!(&reference(&src, &Settings::default())[(W + 1) * 4..][..4], [0; 4]);
+        let (area, _) = render(&src, frame, frame, &Settings::default());
+        assert_eq!(area, Rect::new(1, 1, 5, 4));
+    }
+
+    #[test]
+    fn test_partial_request() {
+        // The output covers the bottom right of the layer; the input is offset from it.
+        let src = sprite();
+        let input = Rect::with_size(10, 20, W, H);
+        let output = Rect::new(14, 22, 18, 26);
+        let settings = Settings::default().with_view(View::Checkerboard);
+        let (area, dst) = render(&src, input, output, &settings);
+        assert_eq!(area, Rect::new(14, 22, 18, 26));
+
+        let full = reference(&src, &settings);
+        for y in 0..4 {
+            let row = &full[((y + 2) * W + 4) * 4..][..16];
+            assert_eq!(&dst[y * 16..][..16], row, "row {y}");
+        }
+    }
+
+    #[test]
+    fn test_output_beyond_input_is_cleared() {
+        let src = sprite();
+        let input = Rect::with_size(0, 0, W, H);
+        let output = Rect::new(4, 4, 12, 8);
+        let (area, dst) = render(&src, input, output, &Settings::new(Mode::Remult));
+        assert_eq!(area, Rect::new(4, 4, 8, 6));
+        let full = reference(&src, &Settings::new(Mode::Remult));
+        for y in 0..4 {
+            let row = &dst[y * 32..][..32];
+            if y < 2 {
+                assert_eq!(&row[..16], &full[((y + 4) * W + 4) * 4..][..16]);
+                assert!(row[16..].iter().all(|&v| v == 0));
+            } else {
+                assert!(row.iter().all(|&v| v == 0));
+            }
+        }
+    }
+
+    #[test]
+    fn test_pattern_origin() {
+        let src = vec![0u8; 16];
+        let view = ImageView::packed(&src, 2, 2, ChannelOrder::Rgba).unwrap();
+        let mut dst = vec![0u8; 16];
+        let settings = Settings::new(Mode::Remult);
+        for (left, expected) in [(0, (0, 6)), (-3, (61, 6)), (130, (2, 6))] {
+            let rect = Rect::with_size(left, 6, 2, 2);
+            let mut dst_view = ImageViewMut::packed(&mut dst, 2, 2, ChannelOrder::Rgba).unwrap();
+            let mut origin = None;
+            render_region::<_, _, BufferError>(&view, rect, &mut dst_view, rect, &settings, |_, _, o| {
+                origin = Some(o);
+                Ok(())
+            }).unwrap();
+            assert_eq!(origin, Some(expected), "{left}");
+        }
+    }
+}
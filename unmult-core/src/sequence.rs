@@ -0,0 +1,287 @@
+//! Per-instance sequence data and its flattened byte format.
+//!
+//! The flattened form is a small header followed by tagged records:
+//!
+//! ```text
+//! b"UNMT"  version: u16 LE  count: u16 LE  { tag: u8  len: u8  payload: [u8; len] } * count
+//! ```
+//!
+//! Every setting is its own record, so data written before a setting existed reads back with
+//! that setting at its default, and records this build does not know are skipped. All numbers
+//! are little-endian so projects move between Windows and macOS unchanged.
+
+use std::fmt;
+
+use crate::params::{ParamId, ParamValue, PARAMS};
+use crate::settings::{Background, Settings};
+
+const MAGIC: &[u8; 4] = b"UNMT";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceError {
+    /// The data does not start with the sequence-data header.
+    BadMagic,
+    /// The data ends inside the header or a record.
+    Truncated,
+    /// A known record has a payload of the wrong size.
+    BadRecord { tag: u8, len: usize },
+    /// The header's version is not the one the host stored with the data.
+    VersionMismatch { host: u16, data: u16 },
+}
+
+impl fmt::Display for SequenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SequenceError::BadMagic => write!(f, "not unmult sequence data"),
+            SequenceError::Truncated => write!(f, "sequence data is truncated"),
+            SequenceError::BadRecord { tag, len } => write!(f, "record {tag:#04x} has an invalid {len} byte payload"),
+            SequenceError::VersionMismatch { host, data } => write!(f, "host says version {host} but the data is version {data}"),
+        }
+    }
+}
+
+impl std::error::Error for SequenceError {}
+
+/// What an effect instance keeps in its sequence data.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SequenceData {
+    /// Settings last seen by the instance.
+    pub settings: Settings,
+}
+
+impl SequenceData {
+    /// Version written by [`SequenceData::flatten`].
+    pub const VERSION: u16 = 1;
+
+    pub fn new(settings: Settings) -> Self {
+        Self { settings }
+    }
+
+    pub fn flatten(&self) -> Vec<u8> {
+        let mut records = Vec::new();
+        for def in PARAMS {
+            let mut payload = Vec::new();
+            match self.settings.get(def.id) {
+                ParamValue::Popup(i) => payload.push(i as u8),
+                ParamValue::Percent(v) => payload.extend_from_slice(&v.to_le_bytes()),
+                ParamValue::Colour(c) => {
+                    for v in [c.red, c.green, c.blue] {
+                        payload.extend_from_slice(&v.to_le_bytes());
+                    }
+                }
+            }
+            records.push((tag(def.id), payload));
+        }
+
+        let mut out = Vec::with_capacity(8 + records.iter().map(|(_, p)| 2 + p.len()).sum::<usize>());
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&Self::VERSION.to_le_bytes());
+        out.extend_from_slice(&(records.len() as u16).to_le_bytes());
+        for (tag, payload) in records {
+            out.push(tag);
+            out.push(payload.len() as u8);
+            out.extend_from_slice(&payload);
+        }
+        out
+    }
+
+    /// Reads data written by any version of [`SequenceData::flatten`]. `version` is the one
+    /// the host stored alongside the bytes and must match their header.
+    ///
+    /// Empty data, which is what instances saved before sequence data existed hand back, reads
+    /// as the defaults.
+    pub fn unflatten(version: u16, bytes: &[u8]) -> Result<Self, SequenceError> {
+        let mut data = SequenceData::default();
+        if bytes.is_empty() {
+            return Ok(data);
+        }
+        let mut reader = Reader(bytes);
+        if reader.take(4)? != MAGIC {
+            return Err(SequenceError::BadMagic);
+        }
+        let data_version = u16::from_le_bytes(reader.array()?);
+        if data_version != version {
+            return Err(SequenceError::VersionMismatch { host: version, data: data_version });
+        }
+        let count = u16::from_le_bytes(reader.array()?);
+        for _ in 0..count {
+            let [tag, len] = reader.array()?;
+            let payload = reader.take(len as usize)?;
+            data.read_record(version, tag, payload)?;
+        }
+        Ok(data)
+    }
+
+    fn read_record(&mut self, version: u16, tag: u8, payload: &[u8]) -> Result<(), SequenceError> {
+        let Some(def) = PARAMS.iter().find(|def| self::tag(def.id) == tag) else {
+            return Ok(());
+        };
+        let value = match (self.settings.get(def.id), payload.len()) {
+            (ParamValue::Popup(_), 1) => ParamValue::Popup(payload[0] as usize),
+            (ParamValue::Percent(_), 4) => ParamValue::Percent(f32_at(payload, 0)),
+            (ParamValue::Colour(_), 12) => ParamValue::Colour(Background::new(f32_at(payload, 0), f32_at(payload, 4), f32_at(payload, 8))),
+            _ => return Err(SequenceError::BadRecord { tag, len: payload.len() }),
+        };
+        self.settings.set(def.id, migrate(version, value));
+        Ok(())
+    }
+}
+
+/// Brings a setting read from `version` data up to [`SequenceData::VERSION`].
+///
+/// Settings added since `version` have no record and stay at their defaults, so this only has
+/// to handle records whose meaning changed:
+///
+/// - Version 0 stored percentages as the sliders show them, 0 to 100, not 0 to 1.
+fn migrate(version: u16, value: ParamValue) -> ParamValue {
+    match (version, value) {
+        (0, ParamValue::Percent(v)) => ParamValue::Percent(v / 100.0),
+        _ => value,
+    }
+}
+
+/// Stable record tag of a setting. Never reuse a tag for a different setting.
+const fn tag(id: ParamId) -> u8 {
+    match id {
+        ParamId::Mode => 1,
+        ParamId::AlphaSource => 2,
+        ParamId::AlphaCombine => 3,
+        ParamId::Background => 4,
+        ParamId::Transfer => 5,
+        ParamId::Hdr => 6,
+        ParamId::BlackPoint => 7,
+        ParamId::WhitePoint => 8,
+        ParamId::Quantize => 9,
+        ParamId::Output => 10,
+        ParamId::View => 11,
+        ParamId::ViewColour => 12,
+    }
+}
+
+fn f32_at(bytes: &[u8], at: usize) -> f32 {
+    f32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SequenceError> {
+        if self.0.len() < len {
+            return Err(SequenceError::Truncated);
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], SequenceError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::{AlphaSource, Levels, Mode, Quantize, Transfer, View};
+
+    fn sample() -> SequenceData {
+        let settings = Settings::new(Mode::Unscreen)
+            .with_alpha_source(AlphaSource::Rec709)
+            .with_transfer(Transfer::Srgb)
+            .with_levels(Levels::new(0.1, 0.9))
+            .with_background(Background::new(0.25, 0.5, 0.75))
+            .with_quantize(Quantize::Dither)
+            .with_view(View::Solid)
+            .with_view_colour(Background::new(1.0, 0.0, 0.5));
+        SequenceData::new(settings)
+    }
+
+    fn record(tag: u8, payload: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag, payload.len() as u8];
+        out.extend_from_slice(payload);
+        out
+    }
+
+    fn with_records(version: u16, records: &[Vec<u8>]) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&version.to_le_bytes());
+        out.extend_from_slice(&(records.len() as u16).to_le_bytes());
+        records.iter().for_each(|r| out.extend_from_slice(r));
+        out
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = sample();
+        assert_eq!(SequenceData::unflatten(SequenceData::VERSION, &data.flatten()), Ok(data));
+        let default = SequenceData::default();
+        assert_eq!(SequenceData::unflatten(SequenceData::VERSION, &default.flatten()), Ok(default));
+    }
+
+    #[test]
+    fn test_header() {
+        let bytes = sample().flatten();
+        assert_eq!(&bytes[..4], b"UNMT");
+        assert_eq!(u16::from_le_bytes([bytes[4], bytes[5]]), SequenceData::VERSION);
+        assert_eq!(u16::from_le_bytes([bytes[6], bytes[7]]) as usize, PARAMS.len());
+    }
+
+    #[test]
+    fn test_empty_is_default() {
+        assert_eq!(SequenceData::unflatten(SequenceData::VERSION, &[]), Ok(SequenceData::default()));
+    }
+
+    #[test]
+    fn test_missing_records_default() {
+        // Data from a version that only knew the mode.
+        let bytes = with_records(0, &[record(tag(ParamId::Mode), &[1])]);
+        let data = SequenceData::unflatten(0, &bytes).unwrap();
+        assert_eq!(data, SequenceData::new(Settings::new(Mode::Remult)));
+    }
+
+    #[test]
+    fn test_unknown_records_skipped() {
+        let bytes = with_records(SequenceData::VERSION + 1, &[
+            record(0x7f, &[1, 2, 3]),
+            record(tag(ParamId::Transfer), &[1]),
+            record(0xff, &[]),
+        ]);
+        let data = SequenceData::unflatten(SequenceData::VERSION + 1, &bytes).unwrap();
+        assert_eq!(data.settings, Settings::default().with_transfer(Transfer::Srgb));
+    }
+
+    #[test]
+    fn test_invalid_data() {
+        assert_eq!(SequenceData::unflatten(SequenceData::VERSION, b"JUNKDATA"), Err(SequenceError::BadMagic));
+        let bytes = sample().flatten();
+        for len in [1, 5, 7, bytes.len() - 1] {
+            assert_eq!(SequenceData::unflatten(SequenceData::VERSION, &bytes[..len]), Err(SequenceError::Truncated), "{len}");
+        }
+        let bytes = with_records(1, &[record(tag(ParamId::BlackPoint), &[0, 0])]);
+        assert_eq!(SequenceData::unflatten(1, &bytes), Err(SequenceError::BadRecord { tag: 7, len: 2 }));
+        assert_eq!(SequenceData::unflatten(0, &bytes), Err(SequenceError::VersionMismatch { host: 0, data: 1 }));
+    }
+
+    #[test]
+    fn test_migrates_version_0_percentages() {
+        let records = |black: f32, white: f32| [
+            record(tag(ParamId::Mode), &[1]),
+            record(tag(ParamId::BlackPoint), &black.to_le_bytes()),
+            record(tag(ParamId::WhitePoint), &white.to_le_bytes()),
+        ];
+        let expected = Settings::new(Mode::Remult).with_levels(Levels::new(0.1, 0.9));
+        let data = SequenceData::unflatten(0, &with_records(0, &records(10.0, 90.0))).unwrap();
+        assert_eq!(data.settings, expected);
+        let data = SequenceData::unflatten(1, &with_records(1, &records(0.1, 0.9))).unwrap();
+        assert_eq!(data.settings, expected);
+    }
+
+    #[test]
+    fn test_tags_unique() {
+        let mut tags: Vec<u8> = PARAMS.iter().map(|def| tag(def.id)).collect();
+        tags.sort();
+        tags.dedup();
+        assert_eq!(tags.len(), PARAMS.len());
+        assert!(tags.iter().all(|&t| t != 0));
+    }
+}
@@ -0,0 +1,581 @@
+//! Options shared by every depth and by the buffer, tile and plugin entry points.
+
+use crate::rgba_to_yuv::max3;
+
+/// Per-pixel operation applied by the buffer and tile APIs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+    /// Black-background colour to straight colour plus alpha.
+    #[default]
+    Unmult,
+    /// Straight colour composited over black, the inverse of `Unmult`.
+    Remult,
+    /// White-background colour to straight colour plus alpha that reproduces it under Multiply.
+    Unscreen,
+}
+
+impl Mode {
+    pub const ALL: [Mode; 3] = [Mode::Unmult, Mode::Remult, Mode::Unscreen];
+}
+
+/// Input levels applied to the max-channel value before it becomes alpha.
+///
+/// Values at or below `black` become transparent, values at or above `white` fully opaque.
+/// The default `0..1` range passes values through untouched, superwhite floats included.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Levels {
+    pub black: f32,
+    pub white: f32,
+}
+
+impl Default for Levels {
+    fn default() -> Self {
+        Self { black: 0.0, white: 1.0 }
+    }
+}
+
+impl Levels {
+    pub fn new(black: f32, white: f32) -> Self {
+        Self { black, white }
+    }
+
+    #[inline]
+    pub fn is_identity(&self) -> bool {
+        self.black == 0.0 && self.white == 1.0
+    }
+
+    /// Maps a normalised max-channel value to alpha.
+    #[inline]
+    pub fn apply(&self, value: f32) -> f32 {
+        if self.is_identity() {
+            return value;
+        }
+        let range = self.white - self.black;
+        if range <= f32::EPSILON {
+            // Collapsed range: a hard threshold at the black point.
+            return if value > self.black { 1.0 } else { 0.0 };
+        }
+        ((value - self.black) / range).clamp(0.0, 1.0)
+    }
+}
+
+/// Which quantity of the colour becomes the matte.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AlphaSource {
+    /// Brightest of red, green and blue.
+    #[default]
+    Max,
+    Rec709,
+    Rec601,
+    Rec2020,
+    Average,
+    Min,
+    Red,
+    Green,
+    Blue,
+}
+
+impl AlphaSource {
+    pub const ALL: [AlphaSource; 9] = [
+        AlphaSource::Max,
+        AlphaSource::Rec709,
+        AlphaSource::Rec601,
+        AlphaSource::Rec2020,
+        AlphaSource::Average,
+        AlphaSource::Min,
+        AlphaSource::Red,
+        AlphaSource::Green,
+        AlphaSource::Blue,
+    ];
+
+    /// Matte value for normalised red, green and blue.
+    #[inline]
+    pub fn matte(self, r: f32, g: f32, b: f32) -> f32 {
+        match self {
+            AlphaSource::Max => max3(r, g, b),
+            AlphaSource::Rec709 => 0.2126 * r + 0.7152 * g + 0.0722 * b,
+            AlphaSource::Rec601 => 0.299 * r + 0.587 * g + 0.114 * b,
+            AlphaSource::Rec2020 => 0.2627 * r + 0.6780 * g + 0.0593 * b,
+            AlphaSource::Average => (r + g + b) / 3.0,
+            AlphaSource::Min => r.min(g).min(b),
+            AlphaSource::Red => r,
+            AlphaSource::Green => g,
+            AlphaSource::Blue => b,
+        }
+    }
+}
+
+/// Colour the element was composited over, normalised to `0..1`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Background {
+    pub red: f32,
+    pub green: f32,
+    pub blue: f32,
+}
+
+impl Background {
+    pub const BLACK: Background = Background { red: 0.0, green: 0.0, blue: 0.0 };
+
+    pub fn new(red: f32, green: f32, blue: f32) -> Self {
+        Self { red, green, blue }
+    }
+
+    #[inline]
+    pub fn is_black(&self) -> bool {
+        *self == Self::BLACK
+    }
+
+    /// How far `value` is from `background` as a fraction of the room between the
+    /// background and the nearest limit, i.e. the least alpha that can produce it. Negative
+    /// values against a zero background are read as zero.
+    #[inline]
+    pub(crate) fn distance(value: f32, background: f32) -> f32 {
+        if value > background {
+            if background >= 1.0 { 0.0 } else { (value - background) / (1.0 - background) }
+        } else if value < background {
+            if background <= 0.0 { 0.0 } else { (background - value) / background }
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Transfer function the input is encoded with.
+///
+/// Anything other than `Linear` is decoded to linear light before alpha is derived and
+/// re-encoded afterwards, so the background and levels are read in linear light as well.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Transfer {
+    /// Process the values as they are.
+    #[default]
+    Linear,
+    Srgb,
+    Rec709,
+    Gamma22,
+    Gamma24,
+}
+
+impl Transfer {
+    pub const ALL: [Transfer; 5] = [Transfer::Linear, Transfer::Srgb, Transfer::Rec709, Transfer::Gamma22, Transfer::Gamma24];
+
+    /// Encoded value to linear light. Negative values are mirrored, superwhites follow the curve.
+    #[inline]
+    pub fn decode(self, v: f32) -> f32 {
+        let x = v.abs();
+        let linear = match self {
+            Transfer::Linear => return v,
+            Transfer::Srgb => if x <= 0.04045 { x / 12.92 } else { ((x + 0.055) / 1.055).powf(2.4) },
+            Transfer::Rec709 => if x < 0.081 { x / 4.5 } else { ((x + 0.099) / 1.099).powf(1.0 / 0.45) },
+            Transfer::Gamma22 => x.powf(2.2),
+            Transfer::Gamma24 => x.powf(2.4),
+        };
+        linear.copysign(v)
+    }
+
+    /// Linear light to encoded value, the inverse of `decode`.
+    #[inline]
+    pub fn encode(self, v: f32) -> f32 {
+        let x = v.abs();
+        let encoded = match self {
+            Transfer::Linear => return v,
+            Transfer::Srgb => if x <= 0.003_130_8 { x * 12.92 } else { 1.055 * x.powf(1.0 / 2.4) - 0.055 },
+            Transfer::Rec709 => if x < 0.018 { x * 4.5 } else { 1.099 * x.powf(0.45) - 0.099 },
+            Transfer::Gamma22 => x.powf(1.0 / 2.2),
+            Transfer::Gamma24 => x.powf(1.0 / 2.4),
+        };
+        encoded.copysign(v)
+    }
+}
+
+/// How mattes above 1.0 (superwhite float input) are turned into alpha.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Hdr {
+    /// Alpha is the matte as is and may exceed 1.
+    #[default]
+    Passthrough,
+    /// Alpha is clamped to 1 and the excess stays in the colour.
+    ClampAlpha,
+    /// The matte is rolled off into `0..1` above `SOFT_KNEE`; colour stays normalised, so
+    /// highlights are tone-mapped.
+    SoftKnee,
+    /// Alpha is rolled off like `SoftKnee` but colour absorbs the difference, so colour times
+    /// alpha still equals the scene-linear input.
+    PreserveEnergy,
+}
+
+impl Hdr {
+    pub const ALL: [Hdr; 4] = [Hdr::Passthrough, Hdr::ClampAlpha, Hdr::SoftKnee, Hdr::PreserveEnergy];
+
+    /// Matte value where the soft knee starts to roll off.
+    pub const SOFT_KNEE: f32 = 0.8;
+
+    /// Smooth roll-off that is the identity below `SOFT_KNEE` and approaches 1 above it.
+    #[inline]
+    pub fn knee(value: f32) -> f32 {
+        if value <= Self::SOFT_KNEE {
+            return value;
+        }
+        // Rational roll-off: slope 1 at the knee, asymptotic to 1.
+        let room = 1.0 - Self::SOFT_KNEE;
+        let over = value - Self::SOFT_KNEE;
+        Self::SOFT_KNEE + room * over / (over + room)
+    }
+
+    /// Splits a matte into `(alpha, divisor)`, where colour is divided by `divisor`.
+    #[inline]
+    pub fn resolve(self, matte: f32) -> (f32, f32) {
+        match self {
+            Hdr::Passthrough => (matte, matte),
+            Hdr::ClampAlpha => (matte.min(1.0), matte.min(1.0)),
+            Hdr::SoftKnee => (Self::knee(matte), matte),
+            Hdr::PreserveEnergy => (Self::knee(matte), Self::knee(matte)),
+        }
+    }
+}
+
+/// How the derived matte combines with the input alpha.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AlphaCombine {
+    /// Historical per-depth behaviour: the 8-bit LUT multiplies, the other depths `Replace`.
+    #[default]
+    Legacy,
+    /// Colour is associated with the input alpha first and the matte derived from it replaces it.
+    Replace,
+    /// Matte from the colour as is, times the input alpha.
+    Multiply,
+    Min,
+    Max,
+    /// Input alpha is dropped; the matte from the colour as is becomes alpha.
+    Ignore,
+}
+
+impl AlphaCombine {
+    pub const ALL: [AlphaCombine; 6] = [
+        AlphaCombine::Legacy,
+        AlphaCombine::Replace,
+        AlphaCombine::Multiply,
+        AlphaCombine::Min,
+        AlphaCombine::Max,
+        AlphaCombine::Ignore,
+    ];
+
+    /// Whether colour is multiplied by the input alpha before the matte is derived.
+    #[inline]
+    pub fn associates_input(self) -> bool {
+        matches!(self, AlphaCombine::Legacy | AlphaCombine::Replace)
+    }
+
+    /// Whether zero input alpha always yields a transparent result.
+    #[inline]
+    pub fn zero_input_is_transparent(self) -> bool {
+        !matches!(self, AlphaCombine::Max | AlphaCombine::Ignore)
+    }
+
+    /// Output alpha from the matte and the normalised input alpha.
+    #[inline]
+    pub fn apply(self, matte: f32, input: f32) -> f32 {
+        match self {
+            AlphaCombine::Legacy | AlphaCombine::Replace | AlphaCombine::Ignore => matte,
+            AlphaCombine::Multiply => matte * input,
+            AlphaCombine::Min => matte.min(input),
+            AlphaCombine::Max => matte.max(input),
+        }
+    }
+}
+
+/// Whether output colour is straight or premultiplied by the output alpha.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Association {
+    #[default]
+    Straight,
+    Premultiplied,
+}
+
+impl Association {
+    pub const ALL: [Association; 2] = [Association::Straight, Association::Premultiplied];
+}
+
+/// What the effect shows. Everything but `Final` is an opaque preview for tuning.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum View {
+    #[default]
+    Final,
+    /// Derived alpha as greyscale.
+    Alpha,
+    /// Result composited over a grey checkerboard.
+    Checkerboard,
+    /// Result composited over `Settings::view_colour`.
+    Solid,
+    /// Result over black at half brightness, with pixels of alpha 0 in blue, alpha 1 in green
+    /// and clipped colour channels in red.
+    Diagnostic,
+}
+
+impl View {
+    pub const ALL: [View; 5] = [View::Final, View::Alpha, View::Checkerboard, View::Solid, View::Diagnostic];
+
+    /// Checkerboard square size in pixels.
+    pub const CHECKER_SIZE: usize = 16;
+
+    /// Grey level of the checkerboard at `x`, `y`.
+    #[inline]
+    pub fn checker(x: usize, y: usize) -> f32 {
+        if (x / Self::CHECKER_SIZE + y / Self::CHECKER_SIZE).is_multiple_of(2) { 0.75 } else { 0.5 }
+    }
+}
+
+/// How float results are turned into integer channel values.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Quantize {
+    /// Round toward zero, the historical behaviour.
+    #[default]
+    Truncate,
+    Round,
+    /// Ordered dither with an 8x8 Bayer matrix keyed on the pixel position.
+    Dither,
+}
+
+const BAYER8: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+impl Quantize {
+    pub const ALL: [Quantize; 3] = [Quantize::Truncate, Quantize::Round, Quantize::Dither];
+
+    /// Fraction of a code value added before truncating, for the pixel at `x`, `y`.
+    #[inline]
+    pub fn offset(self, x: usize, y: usize) -> f32 {
+        match self {
+            Quantize::Truncate => 0.0,
+            Quantize::Round => 0.5,
+            Quantize::Dither => (BAYER8[y % 8][x % 8] as f32 + 0.5) / 64.0,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Settings {
+    pub mode: Mode,
+    pub levels: Levels,
+    pub alpha_source: AlphaSource,
+    pub background: Background,
+    pub transfer: Transfer,
+    pub hdr: Hdr,
+    pub quantize: Quantize,
+    pub alpha_combine: AlphaCombine,
+    pub association: Association,
+    pub view: View,
+    /// Backdrop of `View::Solid`, encoded like the input.
+    pub view_colour: Background,
+}
+
+impl Settings {
+    pub fn new(mode: Mode) -> Self {
+        Self { mode, ..Default::default() }
+    }
+
+    pub fn with_levels(mut self, levels: Levels) -> Self {
+        self.levels = levels;
+        self
+    }
+
+    pub fn with_alpha_source(mut self, alpha_source: AlphaSource) -> Self {
+        self.alpha_source = alpha_source;
+        self
+    }
+
+    pub fn with_background(mut self, background: Background) -> Self {
+        self.background = background;
+        self
+    }
+
+    pub fn with_transfer(mut self, transfer: Transfer) -> Self {
+        self.transfer = transfer;
+        self
+    }
+
+    pub fn with_hdr(mut self, hdr: Hdr) -> Self {
+        self.hdr = hdr;
+        self
+    }
+
+    pub fn with_quantize(mut self, quantize: Quantize) -> Self {
+        self.quantize = quantize;
+        self
+    }
+
+    pub fn with_alpha_combine(mut self, alpha_combine: AlphaCombine) -> Self {
+        self.alpha_combine = alpha_combine;
+        self
+    }
+
+    pub fn with_association(mut self, association: Association) -> Self {
+        self.association = association;
+        self
+    }
+
+    pub fn with_view(mut self, view: View) -> Self {
+        self.view = view;
+        self
+    }
+
+    pub fn with_view_colour(mut self, colour: Background) -> Self {
+        self.view_colour = colour;
+        self
+    }
+
+    /// Whether the matte is the raw max channel over black, which `inner_render` implements.
+    #[inline]
+    pub fn is_max_matte(&self) -> bool {
+        self.alpha_source == AlphaSource::Max
+            && self.levels.is_identity()
+            && self.background.is_black()
+            && self.hdr == Hdr::Passthrough
+    }
+
+    /// Whether this is the plain unmult the LUT and SIMD kernels implement.
+    #[inline]
+    pub fn is_plain_unmult(&self) -> bool {
+        self.mode == Mode::Unmult
+            && self.transfer == Transfer::Linear
+            && self.quantize == Quantize::Truncate
+            && self.alpha_combine == AlphaCombine::Legacy
+            && self.association == Association::Straight
+            && self.view == View::Final
+            && self.is_max_matte()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels() {
+        let levels = Levels::default();
+        assert!(levels.is_identity());
+        assert_eq!(levels.apply(0.3), 0.3);
+        assert_eq!(levels.apply(4.0), 4.0);
+
+        let levels = Levels::new(0.1, 0.6);
+        assert_eq!(levels.apply(0.05), 0.0);
+        assert_eq!(levels.apply(0.1), 0.0);
+        assert!((levels.apply(0.35) - 0.5).abs() < 1e-6);
+        assert_eq!(levels.apply(0.6), 1.0);
+        assert_eq!(levels.apply(2.0), 1.0);
+
+        let levels = Levels::new(0.5, 0.5);
+        assert_eq!(levels.apply(0.5), 0.0);
+        assert_eq!(levels.apply(0.51), 1.0);
+    }
+
+    #[test]
+    fn test_settings_plain_unmult() {
+        assert!(Settings::default().is_plain_unmult());
+        assert!(!Settings::new(Mode::Remult).is_plain_unmult());
+        assert!(!Settings::default().with_levels(Levels::new(0.02, 1.0)).is_plain_unmult());
+        assert!(!Settings::default().with_alpha_source(AlphaSource::Rec709).is_plain_unmult());
+        assert!(!Settings::default().with_background(Background::new(0.0, 0.0, 0.2)).is_plain_unmult());
+        assert!(!Settings::default().with_transfer(Transfer::Srgb).is_plain_unmult());
+        assert!(!Settings::default().with_hdr(Hdr::ClampAlpha).is_plain_unmult());
+        assert!(!Settings::default().with_quantize(Quantize::Round).is_plain_unmult());
+        assert!(!Settings::default().with_alpha_combine(AlphaCombine::Multiply).is_plain_unmult());
+        assert!(!Settings::default().with_association(Association::Premultiplied).is_plain_unmult());
+        assert!(!Settings::default().with_view(View::Alpha).is_plain_unmult());
+    }
+
+    #[test]
+    fn test_alpha_combine() {
+        let (matte, input) = (0.6, 0.5);
+        assert_eq!(AlphaCombine::Legacy.apply(matte, input), 0.6);
+        assert_eq!(AlphaCombine::Replace.apply(matte, input), 0.6);
+        assert_eq!(AlphaCombine::Ignore.apply(matte, input), 0.6);
+        assert_eq!(AlphaCombine::Multiply.apply(matte, input), 0.3);
+        assert_eq!(AlphaCombine::Min.apply(matte, input), 0.5);
+        assert_eq!(AlphaCombine::Max.apply(matte, input), 0.6);
+        assert!(AlphaCombine::Replace.associates_input() && !AlphaCombine::Multiply.associates_input());
+    }
+
+    #[test]
+    fn test_quantize_offset() {
+        assert_eq!(Quantize::Truncate.offset(3, 5), 0.0);
+        assert_eq!(Quantize::Round.offset(3, 5), 0.5);
+
+        // Every threshold of the 8x8 tile is used once, and the tile repeats.
+        let mut offsets: Vec<f32> = (0..64).map(|i| Quantize::Dither.offset(i % 8, i / 8)).collect();
+        assert_eq!(Quantize::Dither.offset(9, 17), Quantize::Dither.offset(1, 1));
+        offsets.sort_by(f32::total_cmp);
+        for (i, o) in offsets.into_iter().enumerate() {
+            assert_eq!(o, (i as f32 + 0.5) / 64.0);
+        }
+    }
+
+    #[test]
+    fn test_hdr_knee() {
+        assert_eq!(Hdr::knee(0.5), 0.5);
+        assert_eq!(Hdr::knee(Hdr::SOFT_KNEE), Hdr::SOFT_KNEE);
+        let mut last = Hdr::SOFT_KNEE;
+        for i in 1..100 {
+            let v = Hdr::knee(Hdr::SOFT_KNEE + i as f32 * 0.1);
+            assert!(v > last && v <= 1.0, "{v}");
+            last = v;
+        }
+        assert!(Hdr::knee(1000.0) <= 1.0);
+    }
+
+    #[test]
+    fn test_transfer_round_trip() {
+        for transfer in Transfer::ALL {
+            assert_eq!(transfer.decode(0.0), 0.0, "{transfer:?}");
+            assert!((transfer.decode(1.0) - 1.0).abs() < 1e-6, "{transfer:?}");
+            for i in -10..=40 {
+                let v = i as f32 / 20.0;
+                let back = transfer.encode(transfer.decode(v));
+                assert!((back - v).abs() < 1e-5, "{transfer:?}: {v} -> {back}");
+            }
+        }
+        assert!((Transfer::Srgb.decode(0.5) - 0.214_041_14).abs() < 1e-6);
+        assert!((Transfer::Rec709.encode(0.18) - 0.409_007_6).abs() < 1e-5);
+        assert!((Transfer::Gamma22.decode(0.5) - 0.217_637_64).abs() < 1e-6);
+        assert_eq!(Transfer::Linear.decode(0.3), 0.3);
+    }
+
+    #[test]
+    fn test_background_distance() {
+        assert_eq!(Background::distance(0.6, 0.0), 0.6);
+        assert_eq!(Background::distance(0.2, 0.2), 0.0);
+        assert!((Background::distance(0.6, 0.2) - 0.5).abs() < 1e-6);
+        assert!((Background::distance(0.1, 0.2) - 0.5).abs() < 1e-6);
+        assert_eq!(Background::distance(0.0, 0.2), 1.0);
+        assert_eq!(Background::distance(1.0, 0.2), 1.0);
+        assert_eq!(Background::distance(1.0, 1.0), 0.0);
+        assert_eq!(Background::distance(0.5, 1.0), 0.5);
+        assert_eq!(Background::distance(-0.25, 0.0), 0.0);
+    }
+
+    #[test]
+    fn test_alpha_source() {
+        let (r, g, b) = (1.0, 0.5, 0.25);
+        assert_eq!(AlphaSource::Max.matte(r, g, b), 1.0);
+        assert_eq!(AlphaSource::Min.matte(r, g, b), 0.25);
+        assert!((AlphaSource::Average.matte(r, g, b) - 0.583_333_3).abs() < 1e-6);
+        assert!((AlphaSource::Rec709.matte(r, g, b) - 0.588_25).abs() < 1e-6);
+        assert!((AlphaSource::Rec601.matte(r, g, b) - 0.621).abs() < 1e-6);
+        assert!((AlphaSource::Rec2020.matte(r, g, b) - 0.6165).abs() < 1e-4);
+        assert_eq!(AlphaSource::Red.matte(r, g, b), r);
+        assert_eq!(AlphaSource::Green.matte(r, g, b), g);
+        assert_eq!(AlphaSource::Blue.matte(r, g, b), b);
+
+        // Every luminance weighting maps white to one.
+        for source in [AlphaSource::Rec709, AlphaSource::Rec601, AlphaSource::Rec2020] {
+            assert!((source.matte(1.0, 1.0, 1.0) - 1.0).abs() < 1e-6, "{source:?}");
+        }
+    }
+}
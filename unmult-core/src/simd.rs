@@ -0,0 +1,548 @@
+//! SIMD unmult kernels for packed rows, selected at runtime.
+//!
+//! Every kernel reproduces the scalar path bit for bit: the 8-bit kernels compute the
+//! `LUT` entries with an exact float division, the 16-bit and float kernels perform the
+//! same operations as [`RgbaPixel::unmult_rgba`](crate::rgba_to_yuv::RgbaPixel::unmult_rgba)
+//! in the same order.
+
+use crate::buffer::ChannelOrder;
+use crate::rgba_to_yuv::{Ae16, PixelCompute};
+use crate::settings::Settings;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SimdLevel {
+    Scalar,
+    Sse41,
+    Avx2,
+    Neon,
+}
+
+impl SimdLevel {
+    pub const ALL: [SimdLevel; 4] = [SimdLevel::Scalar, SimdLevel::Sse41, SimdLevel::Avx2, SimdLevel::Neon];
+
+    /// Best level the running CPU supports.
+    pub fn detect() -> Self {
+        [SimdLevel::Avx2, SimdLevel::Sse41, SimdLevel::Neon]
+            .into_iter()
+            .find(|level| level.is_supported())
+            .unwrap_or(SimdLevel::Scalar)
+    }
+
+    pub fn is_supported(self) -> bool {
+        match self {
+            SimdLevel::Scalar => true,
+            #[cfg(target_arch = "x86_64")]
+            SimdLevel::Sse41 => is_x86_feature_detected!("sse4.1"),
+            #[cfg(target_arch = "x86_64")]
+            SimdLevel::Avx2 => is_x86_feature_detected!("avx2"),
+            SimdLevel::Neon => cfg!(target_arch = "aarch64"),
+            #[allow(unreachable_patterns)]
+            _ => false,
+        }
+    }
+}
+
+/// Reference implementation, one pixel at a time.
+pub fn unmult_row_scalar<T: PixelCompute>(src: &[T], dst: &mut [T], order: ChannelOrder) {
+    for (s, d) in src.chunks_exact(4).zip(dst.chunks_exact_mut(4)) {
+        order.write(d, &T::unmult(&order.read(s), &Settings::default()));
+    }
+}
+
+/// Runs the kernel for `level` and returns how many channels it consumed.
+macro_rules! dispatch {
+    ($level:expr, $src:expr, $dst:expr, $order:expr, $sse:ident, $avx:ident, $neon:ident $(, $arg:expr)*) => {{
+        assert_eq!($src.len(), $dst.len());
+        #[allow(unused_variables)]
+        let done = match $level {
+            #[cfg(target_arch = "x86_64")]
+            SimdLevel::Avx2 if $level.is_supported() => unsafe { x86::$avx($src, $dst, $order $(, $arg)*) },
+            #[cfg(target_arch = "x86_64")]
+            SimdLevel::Sse41 if $level.is_supported() => unsafe { x86::$sse($src, $dst, $order $(, $arg)*) },
+            #[cfg(target_arch = "aarch64")]
+            SimdLevel::Neon => unsafe { neon::$neon($src, $dst, $order $(, $arg)*) },
+            _ => 0,
+        };
+        done
+    }};
+}
+
+/// Unmultiplies a packed 8-bit row. Falls back to scalar code if `level` isn't supported.
+pub fn unmult_row_u8(level: SimdLevel, src: &[u8], dst: &mut [u8], order: ChannelOrder) {
+    let done = dispatch!(level, src, dst, order, unmult_u8_sse41, unmult_u8_avx2, unmult_u8_neon);
+    unmult_row_scalar(&src[done..], &mut dst[done..], order);
+}
+
+/// Unmultiplies a packed 16-bit row. Falls back to scalar code if `level` isn't supported.
+pub fn unmult_row_u16(level: SimdLevel, src: &[u16], dst: &mut [u16], order: ChannelOrder) {
+    let done = dispatch!(level, src, dst, order, unmult_u16_sse41, unmult_u16_avx2, unmult_u16_neon, u16::SCALE);
+    unmult_row_scalar(&src[done..], &mut dst[done..], order);
+}
+
+/// Unmultiplies a packed After Effects 16bpc row, white at 32768, with the 16-bit kernels.
+pub fn unmult_row_ae16(level: SimdLevel, src: &[Ae16], dst: &mut [Ae16], order: ChannelOrder) {
+    // SAFETY: `Ae16` is a transparent `u16`; the raw views are dropped before `src` and
+    // `dst` are used again.
+    let (raw_src, raw_dst) = unsafe {
+        (
+            std::slice::from_raw_parts(src.as_ptr().cast::<u16>(), src.len()),
+            std::slice::from_raw_parts_mut(dst.as_mut_ptr().cast::<u16>(), dst.len()),
+        )
+    };
+    let done = dispatch!(level, raw_src, raw_dst, order, unmult_u16_sse41, unmult_u16_avx2, unmult_u16_neon, Ae16::SCALE);
+    unmult_row_scalar(&src[done..], &mut dst[done..], order);
+}
+
+/// Unmultiplies a packed float row. Falls back to scalar code if `level` isn't supported.
+pub fn unmult_row_f32(level: SimdLevel, src: &[f32], dst: &mut [f32], order: ChannelOrder) {
+    let done = dispatch!(level, src, dst, order, unmult_f32_sse41, unmult_f32_avx2, unmult_f32_neon);
+    unmult_row_scalar(&src[done..], &mut dst[done..], order);
+}
+
+// The kernels below process whole blocks of pixels and return how many channels they
+// consumed; the caller finishes the tail with the scalar path.
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use crate::buffer::ChannelOrder;
+
+    #[inline]
+    #[target_feature(enable = "sse4.1")]
+    fn transpose(v: [__m128; 4]) -> [__m128; 4] {
+        let t0 = _mm_unpacklo_ps(v[0], v[1]);
+        let t1 = _mm_unpacklo_ps(v[2], v[3]);
+        let t2 = _mm_unpackhi_ps(v[0], v[1]);
+        let t3 = _mm_unpackhi_ps(v[2], v[3]);
+        [_mm_movelh_ps(t0, t1), _mm_movehl_ps(t1, t0), _mm_movelh_ps(t2, t3), _mm_movehl_ps(t3, t2)]
+    }
+
+    /// Same as `RgbaPixel::unmult_rgba` on normalised planar channels.
+    #[inline]
+    #[target_feature(enable = "sse4.1")]
+    fn unmult_planar(c: [__m128; 4], [ro, go, bo, ao]: [usize; 4], always_mul: bool) -> [__m128; 4] {
+        let (mut r, mut g, mut b, a) = (c[ro], c[go], c[bo], c[ao]);
+        let zero = _mm_setzero_ps();
+        let one = _mm_set1_ps(1.0);
+        let mul = if always_mul { _mm_castsi128_ps(_mm_set1_epi32(-1)) } else { _mm_cmplt_ps(a, one) };
+        r = _mm_blendv_ps(r, _mm_mul_ps(r, a), mul);
+        g = _mm_blendv_ps(g, _mm_mul_ps(g, a), mul);
+        b = _mm_blendv_ps(b, _mm_mul_ps(b, a), mul);
+
+        let r_is_max = _mm_and_ps(_mm_cmpge_ps(r, g), _mm_cmpge_ps(r, b));
+        let max = _mm_blendv_ps(_mm_blendv_ps(b, g, _mm_cmpge_ps(g, b)), r, r_is_max);
+        let keep = _mm_andnot_ps(_mm_cmpeq_ps(a, zero), _mm_cmpgt_ps(max, zero));
+        let scale = _mm_div_ps(one, max);
+
+        let mut out = [zero; 4];
+        out[ro] = _mm_and_ps(_mm_mul_ps(r, scale), keep);
+        out[go] = _mm_and_ps(_mm_mul_ps(g, scale), keep);
+        out[bo] = _mm_and_ps(_mm_mul_ps(b, scale), keep);
+        out[ao] = _mm_and_ps(max, keep);
+        out
+    }
+
+    /// `LUT[(max << 8) + v]` for four lanes.
+    #[inline]
+    #[target_feature(enable = "sse4.1")]
+    fn lut(v: __m128i, max: __m128, empty: __m128i) -> __m128i {
+        let q = _mm_cvttps_epi32(_mm_div_ps(_mm_cvtepi32_ps(_mm_slli_epi32(v, 8)), max));
+        _mm_andnot_si128(empty, _mm_min_epi32(q, _mm_set1_epi32(0xFF)))
+    }
+
+    #[target_feature(enable = "sse4.1")]
+    pub(super) unsafe fn unmult_u8_sse41(src: &[u8], dst: &mut [u8], order: ChannelOrder) -> usize {
+        let [ro, go, bo, ao] = order.offsets();
+        let n = src.len() / 16 * 16;
+        let mask = _mm_set1_epi32(0xFF);
+        for i in (0..n).step_by(16) {
+            let x = _mm_loadu_si128(src.as_ptr().add(i) as *const __m128i);
+            let c = [
+                _mm_and_si128(x, mask),
+                _mm_and_si128(_mm_srli_epi32(x, 8), mask),
+                _mm_and_si128(_mm_srli_epi32(x, 16), mask),
+                _mm_srli_epi32(x, 24),
+            ];
+            let max = _mm_max_epi32(_mm_max_epi32(c[ro], c[go]), c[bo]);
+            let max_f = _mm_cvtepi32_ps(max);
+            let empty = _mm_cmpeq_epi32(max, _mm_setzero_si128());
+
+            let mut out = c;
+            out[ro] = lut(c[ro], max_f, empty);
+            out[go] = lut(c[go], max_f, empty);
+            out[bo] = lut(c[bo], max_f, empty);
+            out[ao] = _mm_srli_epi32(_mm_mullo_epi32(c[ao], max), 8);
+            let y = _mm_or_si128(
+                _mm_or_si128(out[0], _mm_slli_epi32(out[1], 8)),
+                _mm_or_si128(_mm_slli_epi32(out[2], 16), _mm_slli_epi32(out[3], 24)),
+            );
+            _mm_storeu_si128(dst.as_mut_ptr().add(i) as *mut __m128i, y);
+        }
+        n
+    }
+
+    #[target_feature(enable = "sse4.1")]
+    pub(super) unsafe fn unmult_u16_sse41(src: &[u16], dst: &mut [u16], order: ChannelOrder, white: f32) -> usize {
+        let n = src.len() / 16 * 16;
+        let scale = _mm_set1_ps(white);
+        for i in (0..n).step_by(16) {
+            let lo = _mm_loadu_si128(src.as_ptr().add(i) as *const __m128i);
+            let hi = _mm_loadu_si128(src.as_ptr().add(i + 8) as *const __m128i);
+            let px = [
+                _mm_div_ps(_mm_cvtepi32_ps(_mm_cvtepu16_epi32(lo)), scale),
+                _mm_div_ps(_mm_cvtepi32_ps(_mm_cvtepu16_epi32(_mm_srli_si128(lo, 8))), scale),
+                _mm_div_ps(_mm_cvtepi32_ps(_mm_cvtepu16_epi32(hi)), scale),
+                _mm_div_ps(_mm_cvtepi32_ps(_mm_cvtepu16_epi32(_mm_srli_si128(hi, 8))), scale),
+            ];
+            let px = transpose(unmult_planar(transpose(px), order.offsets(), true));
+            let p0 = _mm_cvttps_epi32(_mm_min_ps(_mm_mul_ps(px[0], scale), scale));
+            let p1 = _mm_cvttps_epi32(_mm_min_ps(_mm_mul_ps(px[1], scale), scale));
+            let p2 = _mm_cvttps_epi32(_mm_min_ps(_mm_mul_ps(px[2], scale), scale));
+            let p3 = _mm_cvttps_epi32(_mm_min_ps(_mm_mul_ps(px[3], scale), scale));
+            _mm_storeu_si128(dst.as_mut_ptr().add(i) as *mut __m128i, _mm_packus_epi32(p0, p1));
+            _mm_storeu_si128(dst.as_mut_ptr().add(i + 8) as *mut __m128i, _mm_packus_epi32(p2, p3));
+        }
+        n
+    }
+
+    #[target_feature(enable = "sse4.1")]
+    pub(super) unsafe fn unmult_f32_sse41(src: &[f32], dst: &mut [f32], order: ChannelOrder) -> usize {
+        let n = src.len() / 16 * 16;
+        for i in (0..n).step_by(16) {
+            let p = src.as_ptr().add(i);
+            let px = [_mm_loadu_ps(p), _mm_loadu_ps(p.add(4)), _mm_loadu_ps(p.add(8)), _mm_loadu_ps(p.add(12))];
+            let px = transpose(unmult_planar(transpose(px), order.offsets(), false));
+            let q = dst.as_mut_ptr().add(i);
+            _mm_storeu_ps(q, px[0]);
+            _mm_storeu_ps(q.add(4), px[1]);
+            _mm_storeu_ps(q.add(8), px[2]);
+            _mm_storeu_ps(q.add(12), px[3]);
+        }
+        n
+    }
+
+    /// 4x4 transpose within each 128-bit lane.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    fn transpose256(v: [__m256; 4]) -> [__m256; 4] {
+        let t0 = _mm256_unpacklo_ps(v[0], v[1]);
+        let t1 = _mm256_unpacklo_ps(v[2], v[3]);
+        let t2 = _mm256_unpackhi_ps(v[0], v[1]);
+        let t3 = _mm256_unpackhi_ps(v[2], v[3]);
+        [
+            _mm256_shuffle_ps(t0, t1, 0x44),
+            _mm256_shuffle_ps(t0, t1, 0xEE),
+            _mm256_shuffle_ps(t2, t3, 0x44),
+            _mm256_shuffle_ps(t2, t3, 0xEE),
+        ]
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    fn unmult_planar256(c: [__m256; 4], [ro, go, bo, ao]: [usize; 4], always_mul: bool) -> [__m256; 4] {
+        let (mut r, mut g, mut b, a) = (c[ro], c[go], c[bo], c[ao]);
+        let zero = _mm256_setzero_ps();
+        let one = _mm256_set1_ps(1.0);
+        let mul = if always_mul { _mm256_castsi256_ps(_mm256_set1_epi32(-1)) } else { _mm256_cmp_ps(a, one, _CMP_LT_OQ) };
+        r = _mm256_blendv_ps(r, _mm256_mul_ps(r, a), mul);
+        g = _mm256_blendv_ps(g, _mm256_mul_ps(g, a), mul);
+        b = _mm256_blendv_ps(b, _mm256_mul_ps(b, a), mul);
+
+        let r_is_max = _mm256_and_ps(_mm256_cmp_ps(r, g, _CMP_GE_OQ), _mm256_cmp_ps(r, b, _CMP_GE_OQ));
+        let max = _mm256_blendv_ps(_mm256_blendv_ps(b, g, _mm256_cmp_ps(g, b, _CMP_GE_OQ)), r, r_is_max);
+        let keep = _mm256_andnot_ps(_mm256_cmp_ps(a, zero, _CMP_EQ_OQ), _mm256_cmp_ps(max, zero, _CMP_GT_OQ));
+        let scale = _mm256_div_ps(one, max);
+
+        let mut out = [zero; 4];
+        out[ro] = _mm256_and_ps(_mm256_mul_ps(r, scale), keep);
+        out[go] = _mm256_and_ps(_mm256_mul_ps(g, scale), keep);
+        out[bo] = _mm256_and_ps(_mm256_mul_ps(b, scale), keep);
+        out[ao] = _mm256_and_ps(max, keep);
+        out
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    fn lut256(v: __m256i, max: __m256, empty: __m256i) -> __m256i {
+        let q = _mm256_cvttps_epi32(_mm256_div_ps(_mm256_cvtepi32_ps(_mm256_slli_epi32(v, 8)), max));
+        _mm256_andnot_si256(empty, _mm256_min_epi32(q, _mm256_set1_epi32(0xFF)))
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn unmult_u8_avx2(src: &[u8], dst: &mut [u8], order: ChannelOrder) -> usize {
+        let [ro, go, bo, ao] = order.offsets();
+        let n = src.len() / 32 * 32;
+        let mask = _mm256_set1_epi32(0xFF);
+        for i in (0..n).step_by(32) {
+            let x = _mm256_loadu_si256(src.as_ptr().add(i) as *const __m256i);
+            let c = [
+                _mm256_and_si256(x, mask),
+                _mm256_and_si256(_mm256_srli_epi32(x, 8), mask),
+                _mm256_and_si256(_mm256_srli_epi32(x, 16), mask),
+                _mm256_srli_epi32(x, 24),
+            ];
+            let max = _mm256_max_epi32(_mm256_max_epi32(c[ro], c[go]), c[bo]);
+            let max_f = _mm256_cvtepi32_ps(max);
+            let empty = _mm256_cmpeq_epi32(max, _mm256_setzero_si256());
+
+            let mut out = c;
+            out[ro] = lut256(c[ro], max_f, empty);
+            out[go] = lut256(c[go], max_f, empty);
+            out[bo] = lut256(c[bo], max_f, empty);
+            out[ao] = _mm256_srli_epi32(_mm256_mullo_epi32(c[ao], max), 8);
+            let y = _mm256_or_si256(
+                _mm256_or_si256(out[0], _mm256_slli_epi32(out[1], 8)),
+                _mm256_or_si256(_mm256_slli_epi32(out[2], 16), _mm256_slli_epi32(out[3], 24)),
+            );
+            _mm256_storeu_si256(dst.as_mut_ptr().add(i) as *mut __m256i, y);
+        }
+        n
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn unmult_u16_avx2(src: &[u16], dst: &mut [u16], order: ChannelOrder, white: f32) -> usize {
+        let n = src.len() / 32 * 32;
+        let scale = _mm256_set1_ps(white);
+        for i in (0..n).step_by(32) {
+            // Each register holds two neighbouring pixels, one per lane.
+            let mut px = [_mm256_setzero_ps(); 4];
+            for (k, v) in px.iter_mut().enumerate() {
+                let x = _mm_loadu_si128(src.as_ptr().add(i + k * 8) as *const __m128i);
+                *v = _mm256_div_ps(_mm256_cvtepi32_ps(_mm256_cvtepu16_epi32(x)), scale);
+            }
+            let px = transpose256(unmult_planar256(transpose256(px), order.offsets(), true));
+            for (k, v) in px.into_iter().enumerate() {
+                let x = _mm256_cvttps_epi32(_mm256_min_ps(_mm256_mul_ps(v, scale), scale));
+                let y = _mm_packus_epi32(_mm256_castsi256_si128(x), _mm256_extracti128_si256(x, 1));
+                _mm_storeu_si128(dst.as_mut_ptr().add(i + k * 8) as *mut __m128i, y);
+            }
+        }
+        n
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn unmult_f32_avx2(src: &[f32], dst: &mut [f32], order: ChannelOrder) -> usize {
+        let n = src.len() / 32 * 32;
+        for i in (0..n).step_by(32) {
+            let p = src.as_ptr().add(i);
+            let px = [_mm256_loadu_ps(p), _mm256_loadu_ps(p.add(8)), _mm256_loadu_ps(p.add(16)), _mm256_loadu_ps(p.add(24))];
+            let px = transpose256(unmult_planar256(transpose256(px), order.offsets(), false));
+            let q = dst.as_mut_ptr().add(i);
+            _mm256_storeu_ps(q, px[0]);
+            _mm256_storeu_ps(q.add(8), px[1]);
+            _mm256_storeu_ps(q.add(16), px[2]);
+            _mm256_storeu_ps(q.add(24), px[3]);
+        }
+        n
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    use crate::buffer::ChannelOrder;
+
+    /// Same as `RgbaPixel::unmult_rgba` on normalised planar channels.
+    #[inline]
+    unsafe fn unmult_planar(c: [float32x4_t; 4], [ro, go, bo, ao]: [usize; 4], always_mul: bool) -> [float32x4_t; 4] {
+        let (mut r, mut g, mut b, a) = (c[ro], c[go], c[bo], c[ao]);
+        let zero = vdupq_n_f32(0.0);
+        let one = vdupq_n_f32(1.0);
+        let mul = if always_mul { vdupq_n_u32(u32::MAX) } else { vcltq_f32(a, one) };
+        r = vbslq_f32(mul, vmulq_f32(r, a), r);
+        g = vbslq_f32(mul, vmulq_f32(g, a), g);
+        b = vbslq_f32(mul, vmulq_f32(b, a), b);
+
+        let r_is_max = vandq_u32(vcgeq_f32(r, g), vcgeq_f32(r, b));
+        let max = vbslq_f32(r_is_max, r, vbslq_f32(vcgeq_f32(g, b), g, b));
+        let keep = vbicq_u32(vcgtq_f32(max, zero), vceqq_f32(a, zero));
+        let scale = vdivq_f32(one, max);
+        let masked = |v: float32x4_t| vreinterpretq_f32_u32(vandq_u32(vreinterpretq_u32_f32(v), keep));
+
+        let mut out = [zero; 4];
+        out[ro] = masked(vmulq_f32(r, scale));
+        out[go] = masked(vmulq_f32(g, scale));
+        out[bo] = masked(vmulq_f32(b, scale));
+        out[ao] = masked(max);
+        out
+    }
+
+    /// `LUT[(max << 8) + v]` for four lanes.
+    #[inline]
+    unsafe fn lut(v: uint32x4_t, max: float32x4_t, empty: uint32x4_t) -> uint32x4_t {
+        let q = vcvtq_u32_f32(vdivq_f32(vcvtq_f32_u32(vshlq_n_u32::<8>(v)), max));
+        vbicq_u32(vminq_u32(q, vdupq_n_u32(0xFF)), empty)
+    }
+
+    pub(super) unsafe fn unmult_u8_neon(src: &[u8], dst: &mut [u8], order: ChannelOrder) -> usize {
+        let [ro, go, bo, ao] = order.offsets();
+        let n = src.len() / 16 * 16;
+        let mask = vdupq_n_u32(0xFF);
+        for i in (0..n).step_by(16) {
+            let x = vreinterpretq_u32_u8(vld1q_u8(src.as_ptr().add(i)));
+            let c = [
+                vandq_u32(x, mask),
+                vandq_u32(vshrq_n_u32::<8>(x), mask),
+                vandq_u32(vshrq_n_u32::<16>(x), mask),
+                vshrq_n_u32::<24>(x),
+            ];
+            let max = vmaxq_u32(vmaxq_u32(c[ro], c[go]), c[bo]);
+            let max_f = vcvtq_f32_u32(max);
+            let empty = vceqq_u32(max, vdupq_n_u32(0));
+
+            let mut out = c;
+            out[ro] = lut(c[ro], max_f, empty);
+            out[go] = lut(c[go], max_f, empty);
+            out[bo] = lut(c[bo], max_f, empty);
+            out[ao] = vshrq_n_u32::<8>(vmulq_u32(c[ao], max));
+            let y = vorrq_u32(
+                vorrq_u32(out[0], vshlq_n_u32::<8>(out[1])),
+                vorrq_u32(vshlq_n_u32::<16>(out[2]), vshlq_n_u32::<24>(out[3])),
+            );
+            vst1q_u8(dst.as_mut_ptr().add(i), vreinterpretq_u8_u32(y));
+        }
+        n
+    }
+
+    pub(super) unsafe fn unmult_u16_neon(src: &[u16], dst: &mut [u16], order: ChannelOrder, white: f32) -> usize {
+        let n = src.len() / 32 * 32;
+        let scale = vdupq_n_f32(white);
+        for i in (0..n).step_by(32) {
+            let x = vld4q_u16(src.as_ptr().add(i));
+            let x = [x.0, x.1, x.2, x.3];
+            let lo = x.map(|v| vdivq_f32(vcvtq_f32_u32(vmovl_u16(vget_low_u16(v))), scale));
+            let hi = x.map(|v| vdivq_f32(vcvtq_f32_u32(vmovl_high_u16(v)), scale));
+            let lo = unmult_planar(lo, order.offsets(), true);
+            let hi = unmult_planar(hi, order.offsets(), true);
+            let pack = |k: usize| {
+                vcombine_u16(
+                    vqmovn_u32(vcvtq_u32_f32(vminq_f32(vmulq_f32(lo[k], scale), scale))),
+                    vqmovn_u32(vcvtq_u32_f32(vminq_f32(vmulq_f32(hi[k], scale), scale))),
+                )
+            };
+            vst4q_u16(dst.as_mut_ptr().add(i), uint16x8x4_t(pack(0), pack(1), pack(2), pack(3)));
+        }
+        n
+    }
+
+    pub(super) unsafe fn unmult_f32_neon(src: &[f32], dst: &mut [f32], order: ChannelOrder) -> usize {
+        let n = src.len() / 16 * 16;
+        for i in (0..n).step_by(16) {
+            let x = vld4q_f32(src.as_ptr().add(i));
+            let y = unmult_planar([x.0, x.1, x.2, x.3], order.offsets(), false);
+            vst4q_f32(dst.as_mut_ptr().add(i), float32x4x4_t(y[0], y[1], y[2], y[3]));
+        }
+        n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORDERS: [ChannelOrder; 3] = [ChannelOrder::Rgba, ChannelOrder::Bgra, ChannelOrder::Argb];
+
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> u32 {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (self.0 >> 33) as u32
+        }
+    }
+
+    fn same_f32(a: &[f32], b: &[f32]) -> bool {
+        a.iter().zip(b).all(|(a, b)| a.to_bits() == b.to_bits() || (a.is_nan() && b.is_nan()))
+    }
+
+    #[test]
+    fn test_detect_is_supported() {
+        assert!(SimdLevel::detect().is_supported());
+        assert!(SimdLevel::Scalar.is_supported());
+    }
+
+    #[test]
+    fn test_simd_u8_matches_scalar() {
+        // Every (value, max) pair of the LUT, plus a ragged tail.
+        let mut src = Vec::new();
+        for max in 0..=255u8 {
+            for v in 0..=max {
+                src.extend_from_slice(&[v, max, v / 2, max.wrapping_mul(31).wrapping_add(v)]);
+            }
+        }
+        src.extend_from_slice(&[10, 20, 30, 40, 250, 0, 3, 255]);
+
+        for order in ORDERS {
+            let mut expected = vec![0u8; src.len()];
+            unmult_row_scalar(&src, &mut expected, order);
+            for level in SimdLevel::ALL.into_iter().filter(|l| l.is_supported()) {
+                let mut out = vec![0u8; src.len()];
+                unmult_row_u8(level, &src, &mut out, order);
+                assert_eq!(out, expected, "{level:?} {order:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_simd_u16_matches_scalar() {
+        let mut rng = Lcg(1);
+        let mut src: Vec<u16> = (0..4 * 4099).map(|_| rng.next() as u16).collect();
+        src[..16].copy_from_slice(&[0, 0, 0, 0, 0xFFFF, 0xFFFF, 0xFFFF, 0xFFFF, 1, 0, 0, 0xFFFF, 0x8000, 0x4000, 0, 1]);
+
+        for order in ORDERS {
+            let mut expected = vec![0u16; src.len()];
+            unmult_row_scalar(&src, &mut expected, order);
+            for level in SimdLevel::ALL.into_iter().filter(|l| l.is_supported()) {
+                let mut out = vec![0u16; src.len()];
+                unmult_row_u16(level, &src, &mut out, order);
+                assert_eq!(out, expected, "{level:?} {order:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_simd_ae16_matches_scalar() {
+        let mut rng = Lcg(3);
+        // Mostly within 0..=32768, with some out-of-range values the host should never send.
+        let mut src: Vec<Ae16> = (0..4 * 4099).map(|_| Ae16((rng.next() % 34000) as u16)).collect();
+        src[..16].copy_from_slice(&[0, 0, 0, 0, 32768, 32768, 32768, 32768, 1, 0, 0, 32768, 0x8000, 0x4000, 0, 1].map(Ae16));
+
+        for order in ORDERS {
+            let mut expected = vec![Ae16(0); src.len()];
+            unmult_row_scalar(&src, &mut expected, order);
+            for level in SimdLevel::ALL.into_iter().filter(|l| l.is_supported()) {
+                let mut out = vec![Ae16(0); src.len()];
+                unmult_row_ae16(level, &src, &mut out, order);
+                assert_eq!(out, expected, "{level:?} {order:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_simd_f32_matches_scalar() {
+        let mut rng = Lcg(2);
+        let mut src: Vec<f32> = (0..4 * 4099).map(|_| (rng.next() as f32 / u32::MAX as f32) * 2.5 - 0.5).collect();
+        src[..32].copy_from_slice(&[
+            0.0, 0.0, 0.0, 0.0,
+            1.0, 1.0, 1.0, 1.0,
+            -0.0, 0.5, 0.25, -0.0,
+            f32::NAN, 0.5, 0.25, 1.0,
+            0.5, 0.5, 0.5, f32::NAN,
+            4.0, 2.0, 0.5, 1.0,
+            -1.0, -2.0, -0.5, 0.5,
+            0.3, 0.3, 0.1, f32::INFINITY,
+        ]);
+
+        for order in ORDERS {
+            let mut expected = vec![0.0f32; src.len()];
+            unmult_row_scalar(&src, &mut expected, order);
+            for level in SimdLevel::ALL.into_iter().filter(|l| l.is_supported()) {
+                let mut out = vec![0.0f32; src.len()];
+                unmult_row_f32(level, &src, &mut out, order);
+                assert!(same_f32(&out, &expected), "{level:?} {order:?}");
+            }
+        }
+    }
+}